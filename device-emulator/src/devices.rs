@@ -0,0 +1,116 @@
+use rand::Rng;
+
+/// Something that produces the same serial output as one of the sketches.
+pub trait Device {
+    /// The next regular measurement line.
+    fn sample(&mut self, rng: &mut dyn rand::RngCore, noise: f64) -> String;
+
+    /// A line the firmware prints when a measurement goes wrong.
+    fn error(&mut self, rng: &mut dyn rand::RngCore) -> String;
+
+    /// Line terminator used by the firmware's print routine.
+    fn line_ending(&self) -> &'static str;
+}
+
+// temp-monitor: MILLIS_INCREMENT = PRESCALER * ticks / 16000 with ticks = 52082,
+// and a measurement is taken every third Timer1 compare.
+const TEMP_MILLIS_INCREMENT: u32 = 1024 * 52082 / 16000;
+const TEMP_MILLIS_PER_SAMPLE: u32 = 3 * TEMP_MILLIS_INCREMENT;
+
+/// Emulates temperature/temp-monitor.
+pub struct TempMonitor {
+    millis: u32,
+    celsius: f64,
+}
+
+impl TempMonitor {
+    pub fn new(start_ms: u32, fahrenheit: f64) -> Self {
+        TempMonitor {
+            millis: start_ms,
+            celsius: (fahrenheit - 32.0) / 1.8,
+        }
+    }
+}
+
+impl Device for TempMonitor {
+    fn sample(&mut self, rng: &mut dyn rand::RngCore, noise: f64) -> String {
+        self.millis = self.millis.wrapping_add(TEMP_MILLIS_PER_SAMPLE);
+
+        // The DHT11 driver reports tenths of a degree Celsius.
+        let jitter = if noise > 0.0 {
+            rng.gen_range(-noise..=noise) / 1.8
+        } else {
+            0.0
+        };
+        let temperature = ((self.celsius + jitter) * 10.0).round() as i16;
+
+        // Same arithmetic and formatting as the firmware, including the
+        // fractional part not being zero padded.
+        let temp_f32 = (((temperature as f32) / 10f32) * 1.8f32) + 32f32 + 0.5f32;
+        let temp_x100 = (temp_f32 * 100f32) as i32;
+        let whole = (temp_x100 / 100) as i16;
+        let frac = (temp_x100 % 100) as i16;
+
+        format!("{},{}.{}", self.millis, whole, frac)
+    }
+
+    fn error(&mut self, rng: &mut dyn rand::RngCore) -> String {
+        // The timestamp still advances when the measurement fails.
+        self.millis = self.millis.wrapping_add(TEMP_MILLIS_PER_SAMPLE);
+
+        match rng.gen_range(0..3) {
+            0 => "Pin Error!",
+            1 => "Checksum Mismatch!",
+            _ => "Timeout!",
+        }
+        .to_string()
+    }
+
+    fn line_ending(&self) -> &'static str {
+        // ufmt's uwriteln! only writes '\n'.
+        "\n"
+    }
+}
+
+/// Emulates tachometer/tacho, including its motor speed sweep.
+pub struct Tacho {
+    speed: u32,
+    min_rpm: f64,
+    max_rpm: f64,
+}
+
+impl Tacho {
+    pub fn new(min_rpm: f64, max_rpm: f64) -> Self {
+        Tacho {
+            speed: 0,
+            min_rpm,
+            max_rpm,
+        }
+    }
+}
+
+impl Device for Tacho {
+    fn sample(&mut self, rng: &mut dyn rand::RngCore, noise: f64) -> String {
+        // The sketch steps the PWM duty by 2 every second and wraps at 155.
+        let rpm = self.min_rpm + (self.max_rpm - self.min_rpm) * self.speed as f64 / 154.0;
+        self.speed = (self.speed + 2) % 155;
+
+        let jitter = if noise > 0.0 {
+            rng.gen_range(-noise..=noise)
+        } else {
+            0.0
+        };
+        ((rpm + jitter).max(0.0) as u32).to_string()
+    }
+
+    fn error(&mut self, _rng: &mut dyn rand::RngCore) -> String {
+        // The sketch has no error messages; its failure mode is a false 0 reading.
+        self.speed = (self.speed + 2) % 155;
+        "0".to_string()
+    }
+
+    fn line_ending(&self) -> &'static str {
+        // Serial.println() terminates with CRLF.
+        "\r\n"
+    }
+}
@@ -0,0 +1,258 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::Duration;
+
+use clap::{Arg, ArgAction, Command};
+use nix::pty::openpty;
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg};
+use nix::unistd::ttyname;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+mod devices;
+
+use devices::{Device, Tacho, TempMonitor};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let matches = Command::new("Device Emulator")
+        .about("Emulates the serial output of the sketches on a pseudo-terminal.")
+        .arg(
+            Arg::new("device")
+                .help("Which firmware to emulate.")
+                .required(true)
+                .value_parser(["temp", "tacho"]),
+        )
+        .arg(
+            Arg::new("interval")
+                .long("interval")
+                .help("Seconds between lines. Defaults to the firmware's own rate.")
+                .value_parser(parse_interval),
+        )
+        .arg(
+            Arg::new("noise")
+                .long("noise")
+                .help("Maximum random deviation added to each reading (°F or RPM).")
+                .default_value("0")
+                .value_parser(parse_noise),
+        )
+        .arg(
+            Arg::new("dropout")
+                .long("dropout")
+                .help("Probability that a line is silently not sent.")
+                .default_value("0")
+                .value_parser(parse_probability),
+        )
+        .arg(
+            Arg::new("error-rate")
+                .long("error-rate")
+                .help("Probability that a measurement fails and an error line is sent instead.")
+                .default_value("0")
+                .value_parser(parse_probability),
+        )
+        .arg(
+            Arg::new("garble")
+                .long("garble")
+                .help("Probability that a line is corrupted on the wire.")
+                .default_value("0")
+                .value_parser(parse_probability),
+        )
+        .arg(
+            Arg::new("temperature")
+                .long("temperature")
+                .help("Base temperature in °F for the temp device.")
+                .default_value("72")
+                .value_parser(clap::value_parser!(f64)),
+        )
+        .arg(
+            Arg::new("start-ms")
+                .long("start-ms")
+                .help("Initial millisecond counter for the temp device.")
+                .default_value("0")
+                .value_parser(clap::value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("min-rpm")
+                .long("min-rpm")
+                .help("RPM at the bottom of the tacho motor sweep.")
+                .default_value("500")
+                .value_parser(clap::value_parser!(f64)),
+        )
+        .arg(
+            Arg::new("max-rpm")
+                .long("max-rpm")
+                .help("RPM at the top of the tacho motor sweep.")
+                .default_value("2200")
+                .value_parser(clap::value_parser!(f64)),
+        )
+        .arg(
+            Arg::new("count")
+                .long("count")
+                .help("Stop after this many lines instead of running forever.")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .help("Seed for the random number generator, for repeatable runs.")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("link")
+                .long("link")
+                .help("Create a symlink to the pseudo-terminal at this path.")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .help("Don't echo the emitted lines.")
+                .action(ArgAction::SetTrue),
+        )
+        .get_matches();
+
+    let (mut device, default_interval): (Box<dyn Device>, f64) = match matches
+        .get_one::<String>("device")
+        .expect("Device is required.")
+        .as_str()
+    {
+        "temp" => (
+            Box::new(TempMonitor::new(
+                *matches
+                    .get_one::<u32>("start-ms")
+                    .expect("Start ms has a default."),
+                *matches
+                    .get_one::<f64>("temperature")
+                    .expect("Temperature has a default."),
+            )),
+            10.0,
+        ),
+        _ => (
+            Box::new(Tacho::new(
+                *matches
+                    .get_one::<f64>("min-rpm")
+                    .expect("Min RPM has a default."),
+                *matches
+                    .get_one::<f64>("max-rpm")
+                    .expect("Max RPM has a default."),
+            )),
+            1.0,
+        ),
+    };
+
+    let interval = matches
+        .get_one::<f64>("interval")
+        .copied()
+        .unwrap_or(default_interval);
+    let noise = *matches
+        .get_one::<f64>("noise")
+        .expect("Noise has a default.");
+    let dropout = *matches
+        .get_one::<f64>("dropout")
+        .expect("Dropout has a default.");
+    let error_rate = *matches
+        .get_one::<f64>("error-rate")
+        .expect("Error rate has a default.");
+    let garble = *matches
+        .get_one::<f64>("garble")
+        .expect("Garble has a default.");
+    let count = matches.get_one::<u64>("count").copied();
+    let quiet = matches.get_flag("quiet");
+
+    let mut rng = match matches.get_one::<u64>("seed") {
+        Some(seed) => StdRng::seed_from_u64(*seed),
+        None => StdRng::from_entropy(),
+    };
+
+    // Open the pty and put the device side in raw mode so line endings pass
+    // through untouched, like a real USB serial adapter.
+    let pty = openpty(None, None)?;
+    let mut termios = tcgetattr(&pty.slave)?;
+    cfmakeraw(&mut termios);
+    tcsetattr(&pty.slave, SetArg::TCSANOW, &termios)?;
+    let path = ttyname(&pty.slave)?;
+
+    if let Some(link) = matches.get_one::<PathBuf>("link") {
+        if link.is_symlink() {
+            std::fs::remove_file(link)?;
+        }
+        std::os::unix::fs::symlink(&path, link)?;
+        println!("Emulating on {} ({})", link.display(), path.display());
+    } else {
+        println!("Emulating on {}", path.display());
+    }
+
+    // Keep our copy of the slave open so the pty survives the recorder
+    // closing and reopening it.
+    let _slave = pty.slave;
+    let mut master = File::from(pty.master);
+
+    let mut sent = 0u64;
+    while count.is_none_or(|count| sent < count) {
+        sleep(Duration::from_secs_f64(interval));
+        sent += 1;
+
+        let line = if rng.gen_bool(error_rate) {
+            device.error(&mut rng)
+        } else {
+            device.sample(&mut rng, noise)
+        };
+
+        if rng.gen_bool(dropout) {
+            continue;
+        }
+
+        let mut bytes = format!("{}{}", line, device.line_ending()).into_bytes();
+        if rng.gen_bool(garble) {
+            garble_bytes(&mut bytes, &mut rng);
+        }
+
+        if !quiet {
+            println!("{}", String::from_utf8_lossy(&bytes).trim_end());
+        }
+        master.write_all(&bytes)?;
+    }
+
+    // Closing the pty throws away anything the reader hasn't picked up yet.
+    sleep(Duration::from_secs_f64(interval));
+    Ok(())
+}
+
+/// A probability for `--dropout`, `--error-rate` and `--garble`.
+fn parse_probability(text: &str) -> Result<f64, String> {
+    text.parse()
+        .ok()
+        .filter(|p: &f64| (0.0..=1.0).contains(p))
+        .ok_or_else(|| format!("Invalid probability {:?}, expected 0 to 1", text))
+}
+
+/// The most `--noise` adds or takes away, which can't be negative.
+fn parse_noise(text: &str) -> Result<f64, String> {
+    text.parse()
+        .ok()
+        .filter(|noise: &f64| noise.is_finite() && *noise >= 0.0)
+        .ok_or_else(|| format!("Invalid noise {:?}, expected 0 or more", text))
+}
+
+/// Seconds between lines, which must be more than zero.
+fn parse_interval(text: &str) -> Result<f64, String> {
+    text.parse()
+        .ok()
+        .filter(|seconds: &f64| seconds.is_finite() && *seconds > 0.0)
+        .ok_or_else(|| format!("Invalid interval {:?}, expected seconds above 0", text))
+}
+
+/// Corrupt a line the way a noisy link would: flip a few bytes and sometimes
+/// lose the end of it.
+fn garble_bytes(bytes: &mut Vec<u8>, rng: &mut StdRng) {
+    for _ in 0..rng.gen_range(1..=3) {
+        let index = rng.gen_range(0..bytes.len());
+        bytes[index] = rng.gen();
+    }
+    if rng.gen_bool(0.5) {
+        bytes.truncate(rng.gen_range(0..bytes.len()));
+    }
+}
@@ -0,0 +1,424 @@
+use std::fmt;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use crate::clock::HostTime;
+use crate::decoder::Value;
+use crate::events::{Event, EventKind};
+use crate::sinks::field_name;
+use crate::stage::Stage;
+use crate::units::parse_duration;
+
+/// What an alarm compares against its threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Measure {
+    /// The value itself.
+    Value,
+    /// How fast the value is changing, in units per second of host time.
+    Rate,
+}
+
+/// Which side of the threshold raises the alarm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Above,
+    Below,
+}
+
+/// One alarm condition, parsed from e.g. `temperature_f>80,hysteresis=2,for=30s`.
+///
+/// The column is followed by `/s` to watch its rate of change instead, as in
+/// `rpm/s<-200`. Options after the condition are `hysteresis=` (how far back
+/// past the threshold the value has to come before the alarm clears), `for=`
+/// (how long the condition has to hold before the alarm is raised) and
+/// `name=`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlarmRule {
+    pub name: String,
+    /// Column header or field name, e.g. "Temperature (°F)" or "temperature_f".
+    pub column: String,
+    pub measure: Measure,
+    pub direction: Direction,
+    pub threshold: f64,
+    pub hysteresis: f64,
+    pub min_duration: Duration,
+}
+
+impl AlarmRule {
+    fn breached(&self, measured: f64) -> bool {
+        match self.direction {
+            Direction::Above => measured > self.threshold,
+            Direction::Below => measured < self.threshold,
+        }
+    }
+
+    /// Where the value has to get back to before the alarm clears.
+    fn clear_at(&self) -> f64 {
+        match self.direction {
+            Direction::Above => self.threshold - self.hysteresis,
+            Direction::Below => self.threshold + self.hysteresis,
+        }
+    }
+
+    /// How a measured value or threshold is written in events.
+    fn show(&self, measured: f64) -> String {
+        match self.measure {
+            Measure::Value => measured.to_string(),
+            Measure::Rate => format!("{:.1}/s", measured),
+        }
+    }
+
+    fn cleared(&self, measured: f64) -> bool {
+        match self.direction {
+            Direction::Above => measured <= self.clear_at(),
+            Direction::Below => measured >= self.clear_at(),
+        }
+    }
+}
+
+impl FromStr for AlarmRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let condition = parts.next().unwrap_or_default();
+
+        let (column, threshold, direction) =
+            if let Some((column, value)) = condition.split_once('>') {
+                (column, value, Direction::Above)
+            } else if let Some((column, value)) = condition.split_once('<') {
+                (column, value, Direction::Below)
+            } else {
+                return Err(format!(
+                    "Invalid alarm {:?}, expected e.g. temperature_f>80 or rpm/s<-200",
+                    s
+                ));
+            };
+        let (column, measure) = match column.trim().strip_suffix("/s") {
+            Some(column) => (column, Measure::Rate),
+            None => (column.trim(), Measure::Value),
+        };
+        let threshold: f64 = threshold
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid threshold {:?} in alarm {:?}", threshold, s))?;
+
+        let mut rule = AlarmRule {
+            name: condition.trim().to_string(),
+            column: column.trim().to_string(),
+            measure,
+            direction,
+            threshold,
+            hysteresis: 0.0,
+            min_duration: Duration::ZERO,
+        };
+        for option in parts {
+            match option.split_once('=') {
+                Some(("hysteresis", value)) => {
+                    rule.hysteresis = value
+                        .trim()
+                        .parse()
+                        .ok()
+                        .filter(|h: &f64| *h >= 0.0)
+                        .ok_or_else(|| format!("Invalid hysteresis {:?} in alarm {:?}", value, s))?
+                }
+                Some(("for", value)) => rule.min_duration = parse_duration(value)?,
+                Some(("name", value)) => rule.name = value.trim().to_string(),
+                _ => {
+                    return Err(format!(
+                        "Unknown option {:?} in alarm {:?}, expected hysteresis=, for= or name=",
+                        option, s
+                    ))
+                }
+            }
+        }
+        Ok(rule)
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Direction::Above => "above",
+            Direction::Below => "below",
+        })
+    }
+}
+
+/// A rule tied to its column, with its progress so far.
+struct Alarm {
+    rule: AlarmRule,
+    column: usize,
+    header: String,
+    active: bool,
+    /// When the condition started holding, while waiting out `min_duration`.
+    breached_since: Option<Duration>,
+    /// The last value and when it arrived, for rates.
+    previous: Option<(Duration, f64)>,
+}
+
+/// Raises and clears alarms as values cross their thresholds, optionally
+/// running a hook command each time.
+///
+/// The hook is run with `sh -c` and told about the alarm through the
+/// `ALARM_NAME`, `ALARM_STATE` (`raised` or `cleared`), `ALARM_CHANNEL`,
+/// `ALARM_VALUE`, `ALARM_THRESHOLD`, `ALARM_TIME` and `ALARM_SESSION_TIME`
+/// environment variables. Recording carries on while it runs.
+pub struct Alarms {
+    alarms: Vec<Alarm>,
+    hook: Option<String>,
+}
+
+impl Alarms {
+    /// `headers` are those of the full row, so rules can watch stage columns
+    /// as well as decoded ones.
+    pub fn new(
+        rules: Vec<AlarmRule>,
+        headers: &[String],
+        hook: Option<String>,
+    ) -> Result<Self, String> {
+        let alarms = rules
+            .into_iter()
+            .map(|rule| {
+                let wanted = field_name(&rule.column);
+                let column = headers
+                    .iter()
+                    .position(|header| *header == rule.column || field_name(header) == wanted)
+                    .ok_or_else(|| {
+                        format!(
+                            "Alarm {:?} watches unknown column {:?}; the columns are {}",
+                            rule.name,
+                            rule.column,
+                            headers.join(", ")
+                        )
+                    })?;
+                Ok(Alarm {
+                    header: headers[column].clone(),
+                    rule,
+                    column,
+                    active: false,
+                    breached_since: None,
+                    previous: None,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Alarms { alarms, hook })
+    }
+
+    /// Start the hook for a raised or cleared alarm without waiting for it.
+    fn run_hook(
+        &self,
+        alarm: &Alarm,
+        event: &Event,
+        measured: f64,
+        received: &HostTime,
+    ) -> Result<(), String> {
+        let Some(hook) = &self.hook else {
+            return Ok(());
+        };
+        let state = match event.kind {
+            EventKind::AlarmRaised => "raised",
+            _ => "cleared",
+        };
+
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(hook)
+            .env("ALARM_NAME", &alarm.rule.name)
+            .env("ALARM_STATE", state)
+            .env("ALARM_CHANNEL", &alarm.header)
+            .env("ALARM_VALUE", measured.to_string())
+            .env("ALARM_THRESHOLD", alarm.rule.threshold.to_string())
+            .env("ALARM_TIME", received.rfc3339())
+            .env("ALARM_SESSION_TIME", received.session_seconds())
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+
+        // Reap it in the background so a slow hook doesn't hold up recording.
+        thread::spawn(move || child.wait());
+        Ok(())
+    }
+}
+
+impl Stage for Alarms {
+    fn headers(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn process(&mut self, received: &HostTime, values: &mut Vec<Value>) -> Vec<Event> {
+        let now = received.elapsed;
+        let mut events = Vec::new();
+
+        for i in 0..self.alarms.len() {
+            let alarm = &mut self.alarms[i];
+            let Some(value) = values.get(alarm.column).and_then(Value::as_f64) else {
+                continue;
+            };
+
+            let measured = match alarm.rule.measure {
+                Measure::Value => value,
+                Measure::Rate => {
+                    let previous = alarm.previous.replace((now, value));
+                    match previous {
+                        Some((then, last)) if now > then => {
+                            (value - last) / (now - then).as_secs_f64()
+                        }
+                        _ => continue,
+                    }
+                }
+            };
+
+            let rule = &alarm.rule;
+            let shown = rule.show(measured);
+            let event = if alarm.active {
+                if !rule.cleared(measured) {
+                    continue;
+                }
+                alarm.active = false;
+                Event::new(
+                    EventKind::AlarmCleared,
+                    format!(
+                        "{}: {} = {} is back {} {}",
+                        rule.name,
+                        alarm.header,
+                        shown,
+                        match rule.direction {
+                            Direction::Above => Direction::Below,
+                            Direction::Below => Direction::Above,
+                        },
+                        rule.show(rule.clear_at()),
+                    ),
+                )
+            } else if rule.breached(measured) {
+                let since = *alarm.breached_since.get_or_insert(now);
+                if now - since < rule.min_duration {
+                    continue;
+                }
+                alarm.active = true;
+                alarm.breached_since = None;
+                Event::new(
+                    EventKind::AlarmRaised,
+                    format!(
+                        "{}: {} = {} is {} {}",
+                        rule.name,
+                        alarm.header,
+                        shown,
+                        rule.direction,
+                        rule.show(rule.threshold),
+                    ),
+                )
+            } else {
+                alarm.breached_since = None;
+                continue;
+            };
+
+            let event = match self.run_hook(&self.alarms[i], &event, measured, received) {
+                Ok(()) => event,
+                Err(e) => Event::new(event.kind, format!("{} (hook failed: {})", event.detail, e)),
+            };
+            events.push(event);
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn alarms(rule: &str) -> Alarms {
+        let headers = vec!["Time (ms)".to_string(), "Temperature (°F)".to_string()];
+        Alarms::new(vec![rule.parse().unwrap()], &headers, None).unwrap()
+    }
+
+    /// Feed the alarms a reading received `seconds` into the session,
+    /// returning the kinds of any events.
+    fn reading(alarms: &mut Alarms, seconds: f64, value: f64) -> Vec<EventKind> {
+        let received = HostTime {
+            wall: Utc::now(),
+            elapsed: Duration::from_secs_f64(seconds),
+        };
+        let mut values = vec![Value::U32((seconds * 1000.0) as u32), Value::F64(value)];
+        let events = alarms.process(&received, &mut values);
+        events.into_iter().map(|event| event.kind).collect()
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rule: AlarmRule = "temperature_f > 80,hysteresis=2,for=30s,name=hot"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            rule,
+            AlarmRule {
+                name: "hot".to_string(),
+                column: "temperature_f".to_string(),
+                measure: Measure::Value,
+                direction: Direction::Above,
+                threshold: 80.0,
+                hysteresis: 2.0,
+                min_duration: Duration::from_secs(30),
+            }
+        );
+        let rule: AlarmRule = "rpm/s<-200".parse().unwrap();
+        assert_eq!(
+            (rule.name.as_str(), rule.column.as_str(), rule.measure),
+            ("rpm/s<-200", "rpm", Measure::Rate)
+        );
+        assert_eq!(rule.threshold, -200.0);
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        for rule in [
+            "temperature_f=80",
+            "temperature_f>hot",
+            "temperature_f>80,hysteresis=-1",
+            "temperature_f>80,for=soon",
+            "temperature_f>80,colour=red",
+        ] {
+            assert!(rule.parse::<AlarmRule>().is_err(), "{}", rule);
+        }
+        let headers = vec!["Temperature (°F)".to_string()];
+        let rule = "humidity>80".parse().unwrap();
+        assert!(Alarms::new(vec![rule], &headers, None).is_err());
+    }
+
+    #[test]
+    fn clears_only_past_the_hysteresis() {
+        let mut alarms = alarms("temperature_f>80,hysteresis=2");
+        assert_eq!(reading(&mut alarms, 0.0, 79.0), []);
+        assert_eq!(reading(&mut alarms, 1.0, 81.0), [EventKind::AlarmRaised]);
+        assert_eq!(reading(&mut alarms, 2.0, 85.0), []);
+        assert_eq!(reading(&mut alarms, 3.0, 79.0), []);
+        assert_eq!(reading(&mut alarms, 4.0, 78.0), [EventKind::AlarmCleared]);
+        assert_eq!(reading(&mut alarms, 5.0, 80.5), [EventKind::AlarmRaised]);
+    }
+
+    #[test]
+    fn ignores_a_spike_shorter_than_for() {
+        let mut alarms = alarms("temperature_f>80,for=10s");
+        assert_eq!(reading(&mut alarms, 0.0, 90.0), []);
+        assert_eq!(reading(&mut alarms, 5.0, 90.0), []);
+        assert_eq!(reading(&mut alarms, 6.0, 70.0), []);
+        // The spike is over, so this breach starts the wait again.
+        assert_eq!(reading(&mut alarms, 7.0, 90.0), []);
+        assert_eq!(reading(&mut alarms, 16.0, 90.0), []);
+        assert_eq!(reading(&mut alarms, 17.0, 90.0), [EventKind::AlarmRaised]);
+    }
+
+    #[test]
+    fn watches_the_rate_of_change() {
+        let mut alarms = alarms("temperature_f/s>1");
+        assert_eq!(reading(&mut alarms, 0.0, 70.0), []);
+        assert_eq!(reading(&mut alarms, 2.0, 71.0), []);
+        assert_eq!(reading(&mut alarms, 4.0, 75.0), [EventKind::AlarmRaised]);
+        assert_eq!(reading(&mut alarms, 6.0, 76.0), [EventKind::AlarmCleared]);
+    }
+}
@@ -0,0 +1,580 @@
+//! Offline analysis of a finished recording: the mean, min and max of every
+//! channel per time bucket, rolling statistics, and a summary of the whole
+//! file, so per-minute averages don't have to be worked out in a spreadsheet.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::{Arg, ArgMatches, Command};
+use serde_json::json;
+
+use crate::recorded::{Channel, Recording};
+use crate::report::{self, ChannelStats};
+use crate::sinks::{self, WriteMode};
+use crate::units::parse_duration;
+
+/// The `analyze` subcommand.
+pub fn command() -> Command {
+    Command::new("analyze")
+        .about("Aggregate, smooth and summarise a CSV file written by a recorder.")
+        .arg(
+            Arg::new("data")
+                .help("The recorded samples.")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("channel")
+                .long("channel")
+                .value_name("COLUMN")
+                .help("Column to analyze, may be repeated. Defaults to every numeric column.")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("every")
+                .long("every")
+                .value_name("DURATION")
+                .help("Length of the buckets to take the mean, min and max over.")
+                .default_value("1m")
+                .value_parser(parse_duration),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .help("Where to write the buckets. Defaults to DATA_buckets.csv.")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("rolling")
+                .long("rolling")
+                .value_name("DURATION")
+                .help(
+                    "Also write the mean, standard deviation, min and max over the \
+                     preceding DURATION at every sample, to DATA_rolling.csv.",
+                )
+                .value_parser(parse_duration),
+        )
+        .arg(
+            Arg::new("summary")
+                .long("summary")
+                .value_name("FILE")
+                .help("Also write the summary as JSON.")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("sample-interval")
+                .long("sample-interval")
+                .value_name("DURATION")
+                .help("Time between rows, for files written without session times.")
+                .default_value("1s")
+                .value_parser(parse_duration),
+        )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .help("Overwrite existing output files.")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+/// Analyze the file named in the `analyze` subcommand's `matches`.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_path = matches
+        .get_one::<PathBuf>("data")
+        .expect("Data is required.");
+    let channels: Vec<String> = matches
+        .get_many::<String>("channel")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    let every = *matches
+        .get_one::<Duration>("every")
+        .expect("Every has a default.");
+    if every.is_zero() {
+        return Err("--every must be longer than zero".into());
+    }
+    if matches
+        .get_one::<Duration>("rolling")
+        .is_some_and(|window| window.is_zero())
+    {
+        return Err("--rolling must be longer than zero".into());
+    }
+    let mode = if matches.get_flag("force") {
+        WriteMode::Overwrite
+    } else {
+        WriteMode::New
+    };
+    let output = matches
+        .get_one::<PathBuf>("output")
+        .cloned()
+        .unwrap_or_else(|| sibling(data_path, "buckets"));
+    let rolling_path = sibling(data_path, "rolling");
+    if mode == WriteMode::New {
+        let rolling = matches.contains_id("rolling").then_some(&rolling_path);
+        let summary = matches.get_one::<PathBuf>("summary");
+        if let Some(path) = [Some(&output), rolling, summary]
+            .into_iter()
+            .flatten()
+            .find(|p| p.exists())
+        {
+            return Err(format!(
+                "{} already exists; pass --force to replace it",
+                path.display()
+            )
+            .into());
+        }
+    }
+
+    let mut recording = Recording::read(data_path, &channels)?;
+    if !recording.timed {
+        let interval = *matches
+            .get_one::<Duration>("sample-interval")
+            .expect("Sample interval has a default.");
+        println!(
+            "No session times in {}, assuming a row every {:.3} s",
+            data_path.display(),
+            interval.as_secs_f64()
+        );
+        recording = space_rows(recording, interval.as_secs_f64());
+    }
+
+    let summary = Summary::of(&data_path.display().to_string(), &recording);
+    print!("{}", summary);
+
+    write_buckets(&output, &recording, every.as_secs_f64(), mode)?;
+    println!("Buckets written to {}", output.display());
+
+    if let Some(window) = matches.get_one::<Duration>("rolling") {
+        write_rolling(&rolling_path, &recording, window.as_secs_f64(), mode)?;
+        println!("Rolling statistics written to {}", rolling_path.display());
+    }
+
+    if let Some(path) = matches.get_one::<PathBuf>("summary") {
+        let json = serde_json::to_string_pretty(&summary.to_json())? + "\n";
+        sinks::open_file(path, mode)?
+            .write_all(json.as_bytes())
+            .map_err(|e| format!("Failed to write summary {}: {}", path.display(), e))?;
+        println!("Summary written to {}", path.display());
+    }
+    Ok(())
+}
+
+/// `data` with `_{suffix}` added to its name, as a CSV file.
+fn sibling(data: &Path, suffix: &str) -> PathBuf {
+    let stem = data.file_stem().unwrap_or_default().to_string_lossy();
+    data.with_file_name(format!("{}_{}.csv", stem, suffix))
+}
+
+/// Turn row numbers into times, for files without session times.
+pub fn space_rows(recording: Recording, interval: f64) -> Recording {
+    Recording {
+        timed: true,
+        channels: recording
+            .channels
+            .into_iter()
+            .map(|channel| Channel {
+                name: channel.name,
+                points: channel
+                    .points
+                    .into_iter()
+                    .map(|(row, y)| (row * interval, y))
+                    .collect(),
+            })
+            .collect(),
+    }
+}
+
+/// The values that fell in one bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    /// Start of the bucket in seconds, a multiple of the bucket width.
+    pub start: f64,
+    pub count: usize,
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Group `points` into buckets `width` seconds wide, aligned to the start of
+/// the session. Buckets without any points are left out.
+pub fn resample(points: &[(f64, f64)], width: f64) -> Vec<Bucket> {
+    let mut buckets: BTreeMap<i64, Vec<f64>> = BTreeMap::new();
+    for &(x, y) in points {
+        let index = (x / width).floor() as i64;
+        buckets.entry(index).or_default().push(y);
+    }
+
+    buckets
+        .into_iter()
+        .map(|(index, values)| Bucket {
+            start: index as f64 * width,
+            count: values.len(),
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        })
+        .collect()
+}
+
+/// Statistics of the values in a window ending at one sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rolling {
+    pub x: f64,
+    pub value: f64,
+    /// How many samples the window held, including this one.
+    pub count: usize,
+    pub mean: f64,
+    /// Sample standard deviation; zero for a single value.
+    pub stddev: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// For every point, the statistics of the points in the `window` seconds up
+/// to and including it.
+pub fn rolling(points: &[(f64, f64)], window: f64) -> Vec<Rolling> {
+    let mut first = 0;
+    points
+        .iter()
+        .enumerate()
+        .map(|(i, &(x, value))| {
+            while first < i && points[first].0 <= x - window {
+                first += 1;
+            }
+            let values: Vec<f64> = points[first..=i].iter().map(|p| p.1).collect();
+            let count = values.len();
+            let mean = values.iter().sum::<f64>() / count as f64;
+            let variance = if count > 1 {
+                values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (count - 1) as f64
+            } else {
+                0.0
+            };
+            Rolling {
+                x,
+                value,
+                count,
+                mean,
+                stddev: variance.sqrt(),
+                min: values.iter().copied().fold(f64::INFINITY, f64::min),
+                max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            }
+        })
+        .collect()
+}
+
+/// Write one row per channel and bucket, from the first bucket with data to
+/// the last. Buckets in between without data get a count of zero, so gaps in
+/// the recording show.
+fn write_buckets(
+    path: &Path,
+    recording: &Recording,
+    width: f64,
+    mode: WriteMode,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(sinks::open_file(path, mode)?);
+    writer.write_record(["Channel", "Bucket Start (s)", "Count", "Mean", "Min", "Max"])?;
+    for channel in &recording.channels {
+        let buckets = resample(&channel.points, width);
+        let Some(first) = buckets.first() else {
+            continue;
+        };
+        let mut expected = first.start;
+        for bucket in &buckets {
+            while expected < bucket.start - width / 2.0 {
+                writer.write_record([&channel.name, &format!("{}", expected), "0", "", "", ""])?;
+                expected += width;
+            }
+            writer.write_record([
+                channel.name.clone(),
+                format!("{}", bucket.start),
+                bucket.count.to_string(),
+                format!("{:.3}", bucket.mean),
+                bucket.min.to_string(),
+                bucket.max.to_string(),
+            ])?;
+            expected = bucket.start + width;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_rolling(
+    path: &Path,
+    recording: &Recording,
+    window: f64,
+    mode: WriteMode,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(sinks::open_file(path, mode)?);
+    writer.write_record([
+        "Channel",
+        "Session Time (s)",
+        "Value",
+        "Count",
+        "Mean",
+        "Std Dev",
+        "Min",
+        "Max",
+    ])?;
+    for channel in &recording.channels {
+        for point in rolling(&channel.points, window) {
+            writer.write_record([
+                channel.name.clone(),
+                format!("{:.3}", point.x),
+                point.value.to_string(),
+                point.count.to_string(),
+                format!("{:.3}", point.mean),
+                format!("{:.3}", point.stddev),
+                point.min.to_string(),
+                point.max.to_string(),
+            ])?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Distribution of every channel over a whole recording.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub source: String,
+    /// Seconds from the first sample to the last.
+    pub duration: f64,
+    pub channels: Vec<ChannelStats>,
+}
+
+impl Summary {
+    pub fn of(source: &str, recording: &Recording) -> Self {
+        let xs = || {
+            recording
+                .channels
+                .iter()
+                .flat_map(|c| c.points.iter().map(|p| p.0))
+        };
+        let first = xs().fold(f64::INFINITY, f64::min);
+        let last = xs().fold(f64::NEG_INFINITY, f64::max);
+        Summary {
+            source: source.to_string(),
+            duration: (last - first).max(0.0),
+            channels: recording
+                .channels
+                .iter()
+                .filter_map(|channel| {
+                    let values: Vec<f64> = channel.points.iter().map(|p| p.1).collect();
+                    ChannelStats::from_values(&channel.name, &values)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "source": self.source,
+            "duration_s": self.duration,
+            "channels": self
+                .channels
+                .iter()
+                .map(ChannelStats::to_json)
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.duration.round() as u64;
+        writeln!(f, "Summary of {}", self.source)?;
+        writeln!(
+            f,
+            "  Duration  {}:{:02}:{:02}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )?;
+        report::write_channel_table(f, &self.channels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A recording from tacho.ino, from before the recorders wrote
+    /// timestamps: a single RPM column, one row a second.
+    fn fixture() -> Recording {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../tachometer/tacho-recorder/rpm_data.csv");
+        Recording::read(&path, &[]).unwrap()
+    }
+
+    fn rpm() -> Vec<(f64, f64)> {
+        let recording = space_rows(fixture(), 1.0);
+        recording.channels[0].points.clone()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn reads_the_fixture_as_untimed_rows() {
+        let recording = fixture();
+        assert!(!recording.timed);
+        assert_eq!(recording.channels.len(), 1);
+        assert_eq!(recording.channels[0].name, "RPM");
+        assert_eq!(recording.channels[0].points.len(), 241);
+        assert_eq!(recording.channels[0].points[8], (8.0, 1260.0));
+    }
+
+    #[test]
+    fn spaces_rows_by_the_sample_interval() {
+        let recording = space_rows(fixture(), 0.5);
+        assert!(recording.timed);
+        assert_eq!(recording.channels[0].points[8], (4.0, 1260.0));
+    }
+
+    #[test]
+    fn resamples_the_fixture_per_minute() {
+        let buckets = resample(&rpm(), 60.0);
+
+        let starts: Vec<f64> = buckets.iter().map(|b| b.start).collect();
+        assert_eq!(starts, [0.0, 60.0, 120.0, 180.0, 240.0]);
+        let counts: Vec<usize> = buckets.iter().map(|b| b.count).collect();
+        assert_eq!(counts, [60, 60, 60, 60, 1]);
+
+        let means = [1175.55, 1227.1167, 1080.1667, 1131.1667, 0.0];
+        for (bucket, mean) in buckets.iter().zip(means) {
+            assert_close(bucket.mean, mean);
+        }
+        assert_eq!((buckets[0].min, buckets[0].max), (0.0, 2580.0));
+        assert_eq!((buckets[1].min, buckets[1].max), (720.0, 2082.0));
+        assert_eq!((buckets[3].min, buckets[3].max), (0.0, 2597.0));
+    }
+
+    #[test]
+    fn leaves_out_empty_buckets() {
+        let points = [(0.0, 1.0), (59.9, 3.0), (125.0, 5.0)];
+        let buckets = resample(&points, 60.0);
+        assert_eq!(
+            buckets,
+            [
+                Bucket {
+                    start: 0.0,
+                    count: 2,
+                    mean: 2.0,
+                    min: 1.0,
+                    max: 3.0
+                },
+                Bucket {
+                    start: 120.0,
+                    count: 1,
+                    mean: 5.0,
+                    min: 5.0,
+                    max: 5.0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rolling_window_covers_the_preceding_samples() {
+        let stats = rolling(&rpm(), 10.0);
+        assert_eq!(stats.len(), 241);
+
+        // The first sample is alone in its window.
+        assert_eq!(stats[0].count, 1);
+        assert_eq!(stats[0].stddev, 0.0);
+
+        // Seconds 0 to 9, then 1 to 10: the motor starts at second 7.
+        assert_eq!(stats[9].count, 10);
+        assert_close(stats[9].mean, 326.0);
+        assert_eq!((stats[9].min, stats[9].max), (0.0, 1260.0));
+        assert_eq!(stats[10].count, 10);
+        assert_close(stats[10].mean, 443.8);
+    }
+
+    #[test]
+    fn rolling_window_always_holds_the_sample_itself() {
+        let stats = rolling(&rpm(), 0.0);
+        assert!(stats.iter().all(|stats| stats.count == 1));
+        assert_eq!(stats[7].mean, stats[7].value);
+    }
+
+    #[test]
+    fn rejects_an_empty_rolling_window() {
+        let data =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../tachometer/tacho-recorder/rpm_data.csv");
+        let matches = command()
+            .try_get_matches_from([
+                Path::new("analyze"),
+                &data,
+                Path::new("--rolling"),
+                Path::new("0"),
+            ])
+            .unwrap();
+        assert!(run(&matches).is_err());
+    }
+
+    #[test]
+    fn refuses_an_existing_summary_before_writing_anything() {
+        let dir = std::env::temp_dir().join(format!("analyze-summary-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let buckets = dir.join("buckets.csv");
+        let summary = dir.join("summary.json");
+        std::fs::write(&summary, "{}").unwrap();
+
+        let data =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../tachometer/tacho-recorder/rpm_data.csv");
+        let matches = command()
+            .try_get_matches_from([
+                Path::new("analyze"),
+                &data,
+                Path::new("-o"),
+                &buckets,
+                Path::new("--summary"),
+                &summary,
+            ])
+            .unwrap();
+        let error = run(&matches).unwrap_err().to_string();
+        assert!(error.contains("summary.json already exists"), "{}", error);
+        assert!(!buckets.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rolling_stddev_is_the_sample_stddev() {
+        let points = [(0.0, 2.0), (1.0, 4.0), (2.0, 4.0), (3.0, 4.0), (4.0, 6.0)];
+        let stats = rolling(&points, 100.0);
+        assert_close(stats[4].mean, 4.0);
+        assert_close(stats[4].stddev, 2.0_f64.sqrt());
+    }
+
+    #[test]
+    fn summarises_the_fixture() {
+        let summary = Summary::of("rpm_data.csv", &space_rows(fixture(), 1.0));
+        assert_eq!(summary.duration, 240.0);
+        assert_eq!(summary.channels.len(), 1);
+
+        let rpm = &summary.channels[0];
+        assert_eq!(rpm.count, 241);
+        assert_eq!((rpm.min, rpm.max), (0.0, 2597.0));
+        assert_close(rpm.mean, 1148.7137);
+
+        let json = summary.to_json();
+        assert_eq!(json["duration_s"], 240.0);
+        assert_eq!(json["channels"][0]["name"], "RPM");
+        assert_eq!(json["channels"][0]["count"], 241);
+    }
+}
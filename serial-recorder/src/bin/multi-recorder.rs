@@ -0,0 +1,11 @@
+use std::error::Error;
+
+use serial_recorder::multi;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let matches = multi::command("Multi-device Recorder")
+        .about("Records several devices at once into one merged, time-ordered file.")
+        .get_matches();
+
+    multi::run(&matches)
+}
@@ -0,0 +1,637 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Local, Utc};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::flag;
+
+use crate::alarm::{AlarmRule, Alarms};
+use crate::analyze;
+use crate::clock::SessionClock;
+use crate::commands::{self, CommandChannel, Step};
+use crate::dashboard::Dashboard;
+use crate::decoder::LineDecoder;
+use crate::events::EventKind;
+use crate::filter::{FilterRule, Filters};
+use crate::metrics::MetricsServer;
+use crate::plot;
+use crate::port::{self, PortMatch, SerialSource};
+use crate::recorder::{Recorder, RecorderOptions};
+use crate::replay::{parse_speed, Replay};
+use crate::sinks::{self, MqttConfig, MqttSink, RotatingSink, Rotation, Sink, WriteMode};
+use crate::source::Source;
+use crate::stage::Stage;
+use crate::units::{parse_bytes, parse_duration, parse_interval};
+use crate::watchdog::Watchdog;
+
+/// Baud rate used with `--match` when `--baud` isn't given; all of the
+/// sketches talk at 9600.
+pub(crate) const DEFAULT_BAUD: u32 = 9600;
+
+/// Longest the serial port is left blocked in a read while commands are being
+/// sent, so replies that never come are noticed in time.
+const COMMAND_POLL: Duration = Duration::from_millis(100);
+
+/// Exit status when `--exit-on-stale` ends a recording.
+pub const STALE_EXIT_CODE: i32 = 3;
+
+/// The command line shared by all of the recorders.
+pub fn command(name: &'static str) -> Command {
+    Command::new(name)
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommands(subcommands())
+        .arg(
+            Arg::new("port")
+                .help("The serial port to listen to.")
+                .use_value_delimiter(false)
+                .required_unless_present_any(["replay", "match"]),
+        )
+        .arg(
+            Arg::new("baud")
+                .help("The baud rate to listen at.")
+                .use_value_delimiter(false)
+                .required_unless_present_any(["replay", "match"])
+                .value_parser(clap::value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("match")
+                .long("match")
+                .value_name("VID:PID[:SERIAL]")
+                .help("Find the port by USB vendor/product ID (hex) and serial number.")
+                .conflicts_with_all(["port", "baud", "replay"])
+                .value_parser(clap::value_parser!(PortMatch)),
+        )
+        .arg(
+            Arg::new("baud-rate")
+                .long("baud")
+                .value_name("BAUD")
+                .help(format!(
+                    "The baud rate to use with --match. Defaults to {}.",
+                    DEFAULT_BAUD
+                ))
+                .requires("match")
+                .value_parser(clap::value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("replay")
+                .long("replay")
+                .value_name("FILE")
+                .help("Read a raw serial capture instead of a live port.")
+                .conflicts_with_all(["port", "baud"])
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("speed")
+                .long("speed")
+                .help("Replay speed multiplier; 0 replays as fast as possible.")
+                .requires("replay")
+                .default_value("1")
+                .value_parser(parse_speed),
+        )
+        .arg(
+            Arg::new("sink")
+                .long("sink")
+                .value_name("KIND[=PATH]")
+                .help(format!(
+                    "Output format to write, may be repeated. One of {}.",
+                    sinks::SINK_KINDS.join(", ")
+                ))
+                .action(ArgAction::Append)
+                .default_value("csv"),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("TEMPLATE")
+                .help(
+                    "Output file. {date}, {time}, {port} and {device} are replaced with \
+                     the start date, start time, port name and device name.",
+                ),
+        )
+        .arg(
+            Arg::new("append")
+                .long("append")
+                .help("Continue existing output files if their columns match.")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .help("Overwrite existing output files.")
+                .conflicts_with("append")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(Arg::new("report").long("report").value_name("FILE").help(
+            "Also write the end-of-session report here, as JSON if the name ends \
+                     in .json. Takes the same placeholders as --output.",
+        ))
+        .args(rotation_args())
+        .arg(
+            Arg::new("filter")
+                .long("filter")
+                .value_name("RULE")
+                .help(
+                    "Also write a filtered copy of a column, e.g. rpm:median=5, rpm:mean=10, \
+                     temperature_f:ema=0.2 or rpm:hampel=7 to replace outliers, with options \
+                     ,sigmas=N for hampel and ,name=NAME. May be repeated.",
+                )
+                .action(ArgAction::Append)
+                .value_parser(clap::value_parser!(FilterRule)),
+        )
+        .arg(
+            Arg::new("alarm")
+                .long("alarm")
+                .value_name("RULE")
+                .help(
+                    "Raise an alarm when a column leaves a band, e.g. temperature_f>80 or \
+                     rpm<600. Append /s to the column to watch its rate of change, and \
+                     options ,hysteresis=N ,for=DURATION ,name=NAME. May be repeated.",
+                )
+                .action(ArgAction::Append)
+                .value_parser(clap::value_parser!(AlarmRule)),
+        )
+        .arg(
+            Arg::new("alarm-hook")
+                .long("alarm-hook")
+                .value_name("COMMAND")
+                .help(
+                    "Shell command run whenever an alarm is raised or cleared, with the \
+                     details in ALARM_NAME, ALARM_STATE, ALARM_CHANNEL, ALARM_VALUE, \
+                     ALARM_THRESHOLD, ALARM_TIME and ALARM_SESSION_TIME.",
+                )
+                .requires("alarm"),
+        )
+        .arg(
+            Arg::new("expect-every")
+                .long("expect-every")
+                .value_name("DURATION")
+                .help("How often the device should send a sample. Defaults to its usual rate.")
+                .value_parser(parse_interval),
+        )
+        .arg(
+            Arg::new("stale-after")
+                .long("stale-after")
+                .value_name("N")
+                .help("Record a stale event after N expected intervals without a valid sample.")
+                .default_value("3")
+                .value_parser(clap::value_parser!(u32).range(1..)),
+        )
+        .arg(
+            Arg::new("exit-on-stale")
+                .long("exit-on-stale")
+                .help(format!(
+                    "Stop recording and exit with status {} when the data goes stale.",
+                    STALE_EXIT_CODE
+                ))
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("send")
+                .long("send")
+                .value_name("COMMAND")
+                .help(
+                    "Send a line to the device at the start of the session. Append \
+                     \"=> REPLY\" to wait for a line starting with REPLY. May be repeated.",
+                )
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("script")
+                .long("script")
+                .value_name("FILE")
+                .help(
+                    "Send the commands in a file, one per line, after any --send. \
+                     \"@wait 2s\" pauses and \"@timeout 5s\" changes the reply timeout.",
+                )
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("stdin-commands")
+                .long("stdin-commands")
+                .help("Send each line typed on standard input to the device as a command.")
+                .conflicts_with("tui")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("reply-timeout")
+                .long("reply-timeout")
+                .value_name("DURATION")
+                .help("How long to wait for a reply to a command.")
+                .default_value("2s")
+                .value_parser(parse_duration),
+        )
+        .arg(
+            Arg::new("metrics")
+                .long("metrics")
+                .value_name("ADDRESS")
+                .help(
+                    "Serve the latest values and counters for Prometheus at \
+                     http://ADDRESS/metrics. A bare port listens on localhost only.",
+                ),
+        )
+        .args(mqtt_args())
+        .arg(
+            Arg::new("tui")
+                .long("tui")
+                .help("Show a live dashboard instead of printing every sample.")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("window")
+                .long("window")
+                .value_name("DURATION")
+                .help("Windows for the dashboard's min/max/mean, e.g. 30s, 5m or 1h.")
+                .requires("tui")
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .default_value("10s,1m,10m")
+                .value_parser(parse_duration),
+        )
+}
+
+/// The subcommands every recorder has besides recording.
+pub(crate) fn subcommands() -> [Command; 3] {
+    [
+        Command::new("list").about("List serial ports with their USB details."),
+        plot::command(),
+        analyze::command(),
+    ]
+}
+
+/// Run the `list`, `plot` or `analyze` subcommand if one was given, or
+/// return `None` to go on and record.
+pub fn run_subcommand(matches: &ArgMatches) -> Option<Result<(), Box<dyn Error>>> {
+    match matches.subcommand() {
+        Some(("list", _)) => Some(port::list_ports()),
+        Some(("plot", plot_matches)) => Some(plot::run(plot_matches)),
+        Some(("analyze", analyze_matches)) => Some(analyze::run(analyze_matches)),
+        _ => None,
+    }
+}
+
+/// Open the port or capture named on the command line and record it with
+/// `decoder`, passing each sample through `stages`.
+pub fn run(
+    matches: &ArgMatches,
+    decoder: Box<dyn LineDecoder>,
+    mut stages: Vec<Box<dyn Stage>>,
+    options: RecorderOptions,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(result) = run_subcommand(matches) {
+        return result;
+    }
+
+    let source = if let Some(path) = matches.get_one::<PathBuf>("replay") {
+        path.display().to_string()
+    } else if let Some(port_match) = matches.get_one::<PortMatch>("match") {
+        let port_name = port_match.find()?;
+        println!("Found {} at {}", port_match, port_name);
+        port_name
+    } else {
+        matches
+            .get_one::<String>("port")
+            .expect("Port is required.")
+            .clone()
+    };
+    if let Some(rules) = matches.get_many::<FilterRule>("filter") {
+        let mut headers = decoder.headers();
+        headers.extend(stages.iter().flat_map(|stage| stage.headers()));
+        stages.push(Box::new(Filters::new(rules.cloned().collect(), &headers)?));
+    }
+    if let Some(rules) = matches.get_many::<AlarmRule>("alarm") {
+        // Alarms run last so they can watch the other stages' columns too.
+        let mut headers = decoder.headers();
+        headers.extend(stages.iter().flat_map(|stage| stage.headers()));
+        let hook = matches.get_one::<String>("alarm-hook").cloned();
+        stages.push(Box::new(Alarms::new(
+            rules.cloned().collect(),
+            &headers,
+            hook,
+        )?));
+    }
+
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+    // Every file named after the start time gets the same one.
+    let clock = SessionClock::start();
+    for sink in sinks_from_matches(matches, &options, &source, clock.started_at())? {
+        sinks.push(sink);
+    }
+
+    let stop = stop_on_signals()?;
+
+    if let Some(mqtt) = mqtt_from_matches(matches)? {
+        sinks.push(Box::new(mqtt));
+    }
+    if let Some(address) = matches.get_one::<String>("metrics") {
+        sinks.push(Box::new(MetricsServer::serve(address)?.sink()));
+    }
+
+    let tui = matches.get_flag("tui");
+    if tui {
+        let windows = matches
+            .get_many::<Duration>("window")
+            .expect("Window has a default.")
+            .copied()
+            .collect();
+        // Chart the last decoded column, which is the measurement itself for
+        // all of the devices.
+        let chart_column = decoder.headers().len().saturating_sub(1);
+        sinks.push(Box::new(Dashboard::new(
+            windows,
+            chart_column,
+            Arc::clone(&stop),
+        )));
+    }
+
+    let commands = commands_from_matches(matches)?;
+
+    let mut input: Box<dyn Source> = if let Some(path) = matches.get_one::<PathBuf>("replay") {
+        let speed = *matches
+            .get_one::<f64>("speed")
+            .expect("Speed has a default.");
+        Box::new(Replay::open(path, options.interval, speed)?)
+    } else {
+        let port_name = &source;
+        let baud_rate = matches
+            .get_one::<u32>("baud")
+            .or(matches.get_one::<u32>("baud-rate"))
+            .copied()
+            .unwrap_or(DEFAULT_BAUD);
+
+        let timeout = match commands {
+            Some(_) => options.timeout.min(COMMAND_POLL),
+            None => options.timeout,
+        };
+
+        // Open the serial port before any dashboard takes over the terminal.
+        match SerialSource::open(port_name, baud_rate, timeout) {
+            Ok(port) => Box::new(port),
+            Err(e) => {
+                eprintln!("Failed to open port {}. Error: {}", port_name, e);
+                std::process::exit(-1);
+            }
+        }
+    };
+
+    let mut recorder =
+        Recorder::with_clock(decoder, stages, sinks, &options.device, &source, clock)?;
+    recorder.set_quiet(tui);
+    recorder.set_stop_flag(stop);
+    let expected = matches
+        .get_one::<Duration>("expect-every")
+        .copied()
+        .unwrap_or(options.interval);
+    let intervals = *matches
+        .get_one::<u32>("stale-after")
+        .expect("Stale-after has a default.");
+    let exit_on_stale = matches.get_flag("exit-on-stale");
+    recorder.set_watchdog(Watchdog::new(expected, intervals, exit_on_stale));
+    if let Some(commands) = commands {
+        recorder.set_commands(commands);
+    }
+    let report = recorder.run(input.as_mut())?;
+
+    print!("{}", report);
+    if let Some(template) = matches.get_one::<String>("report") {
+        let path = PathBuf::from(expand_template(
+            template,
+            &source,
+            &options.device,
+            report.started_at,
+        ));
+        report.write(&path)?;
+        println!("Report written to {}", path.display());
+    }
+
+    if exit_on_stale && report.events.contains_key(&EventKind::Stale) {
+        std::process::exit(STALE_EXIT_CODE);
+    }
+    Ok(())
+}
+
+/// A flag set by the first Ctrl-C or SIGTERM, to stop recording cleanly. A
+/// second Ctrl-C while it's winding down exits straight away.
+pub(crate) fn stop_on_signals() -> Result<Arc<AtomicBool>, Box<dyn Error>> {
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        flag::register_conditional_shutdown(signal, 1, Arc::clone(&stop))?;
+        flag::register(signal, Arc::clone(&stop))?;
+    }
+    Ok(stop)
+}
+
+/// Queue the commands from `--send` and `--script`, and `--stdin-commands`,
+/// or `None` if there aren't any.
+fn commands_from_matches(matches: &ArgMatches) -> Result<Option<CommandChannel>, Box<dyn Error>> {
+    let timeout = *matches
+        .get_one::<Duration>("reply-timeout")
+        .expect("Reply timeout has a default.");
+
+    let mut steps = Vec::new();
+    for spec in matches.get_many::<String>("send").into_iter().flatten() {
+        steps.push(Step::Send(commands::Command::parse(spec, timeout)?));
+    }
+    if let Some(path) = matches.get_one::<PathBuf>("script") {
+        steps.extend(commands::read_script(path, timeout)?);
+    }
+
+    let stdin = matches.get_flag("stdin-commands");
+    if steps.is_empty() && !stdin {
+        return Ok(None);
+    }
+    let mut channel = CommandChannel::new(steps);
+    if stdin {
+        channel.read_stdin(timeout);
+    }
+    Ok(Some(channel))
+}
+
+/// The options for rotating output files, shared by every recorder.
+pub(crate) fn rotation_args() -> [Arg; 4] {
+    [
+        Arg::new("rotate-every")
+            .long("rotate-every")
+            .value_name("DURATION")
+            .help(
+                "Start new output files every DURATION, counted from midnight, e.g. 1d or \
+                 6h. With any of the rotation options, SIGHUP starts new files too.",
+            )
+            .value_parser(parse_duration),
+        Arg::new("rotate-size")
+            .long("rotate-size")
+            .value_name("SIZE")
+            .help("Start new output files once one reaches SIZE, e.g. 100M.")
+            .value_parser(parse_bytes),
+        Arg::new("compress")
+            .long("compress")
+            .help("Gzip output files once they've been rotated.")
+            .action(ArgAction::SetTrue),
+        Arg::new("keep")
+            .long("keep")
+            .value_name("N")
+            .help("Rotated files to keep of each output; older ones are deleted.")
+            .value_parser(clap::value_parser!(u64).range(1..)),
+    ]
+}
+
+/// How to rotate output files, if any of the rotation options were given.
+pub(crate) fn rotation_from_matches(matches: &ArgMatches) -> Option<Rotation> {
+    let rotation = Rotation {
+        every: matches.get_one::<Duration>("rotate-every").copied(),
+        max_size: matches.get_one::<u64>("rotate-size").copied(),
+        compress: matches.get_flag("compress"),
+        keep: matches.get_one::<u64>("keep").map(|keep| *keep as usize),
+    };
+    let enabled = rotation.every.is_some()
+        || rotation.max_size.is_some()
+        || rotation.compress
+        || rotation.keep.is_some();
+    enabled.then_some(rotation)
+}
+
+/// Wrap `sink` to rotate its files, also whenever the process gets SIGHUP.
+pub(crate) fn rotating(
+    sink: Box<dyn Sink + Send>,
+    rotation: &Rotation,
+) -> Result<Box<dyn Sink + Send>, Box<dyn Error>> {
+    let rotate_now = Arc::new(AtomicBool::new(false));
+    flag::register(SIGHUP, Arc::clone(&rotate_now))?;
+    Ok(Box::new(RotatingSink::new(
+        sink,
+        rotation.clone(),
+        rotate_now,
+    )))
+}
+
+/// The options for publishing to MQTT, shared by every recorder.
+pub(crate) fn mqtt_args() -> [Arg; 4] {
+    [
+        Arg::new("mqtt")
+            .long("mqtt")
+            .value_name("HOST[:PORT]")
+            .help("Publish every sample and event to this MQTT broker."),
+        Arg::new("mqtt-topic")
+            .long("mqtt-topic")
+            .value_name("PREFIX")
+            .help("Publish to PREFIX/DEVICE/CHANNEL and PREFIX/DEVICE/events/KIND.")
+            .requires("mqtt")
+            .default_value("recorders"),
+        Arg::new("mqtt-qos")
+            .long("mqtt-qos")
+            .value_name("QOS")
+            .help("MQTT quality of service: 0, 1 or 2.")
+            .requires("mqtt")
+            .default_value("1")
+            .value_parser(clap::value_parser!(u8).range(0..=2)),
+        Arg::new("mqtt-buffer")
+            .long("mqtt-buffer")
+            .value_name("N")
+            .help("Messages kept while the broker is unreachable; the oldest are dropped.")
+            .requires("mqtt")
+            .default_value("10000")
+            .value_parser(clap::value_parser!(u64).range(1..)),
+    ]
+}
+
+/// The MQTT sink asked for with `--mqtt`, if any.
+pub(crate) fn mqtt_from_matches(matches: &ArgMatches) -> Result<Option<MqttSink>, String> {
+    let Some(broker) = matches.get_one::<String>("mqtt") else {
+        return Ok(None);
+    };
+    let (host, port) = MqttConfig::broker(broker)?;
+    let qos = *matches
+        .get_one::<u8>("mqtt-qos")
+        .expect("MQTT QoS has a default.");
+    Ok(Some(MqttSink::new(MqttConfig {
+        host,
+        port,
+        prefix: matches
+            .get_one::<String>("mqtt-topic")
+            .expect("MQTT topic has a default.")
+            .clone(),
+        qos: rumqttc::qos(qos).map_err(|e| e.to_string())?,
+        buffer: *matches
+            .get_one::<u64>("mqtt-buffer")
+            .expect("MQTT buffer has a default.") as usize,
+    })))
+}
+
+/// Build the sinks picked with `--sink`, honouring `--output`, `--append` and
+/// `--force`. Without either, fails if any of their files already exists.
+pub(crate) fn sinks_from_matches(
+    matches: &ArgMatches,
+    options: &RecorderOptions,
+    source: &str,
+    started_at: DateTime<Utc>,
+) -> Result<Vec<Box<dyn Sink + Send>>, Box<dyn Error>> {
+    let mode = if matches.get_flag("append") {
+        WriteMode::Append
+    } else if matches.get_flag("force") {
+        WriteMode::Overwrite
+    } else {
+        WriteMode::New
+    };
+
+    let (output, events) = match matches.get_one::<String>("output") {
+        Some(template) => {
+            let output = PathBuf::from(expand_template(
+                template,
+                source,
+                &options.device,
+                started_at,
+            ));
+            let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+            let events = output.with_file_name(format!("{}_events.csv", stem));
+            (output, events)
+        }
+        None => (options.output.clone(), options.events.clone()),
+    };
+
+    let rotation = rotation_from_matches(matches);
+    let mut sinks = Vec::new();
+    for spec in matches
+        .get_many::<String>("sink")
+        .expect("Sink has a default.")
+    {
+        let spec = expand_template(spec, source, &options.device, started_at);
+        let sink = sinks::from_spec(&spec, &output, &events, mode)?;
+        sinks.push(match &rotation {
+            Some(rotation) => rotating(sink, rotation)?,
+            None => sink,
+        });
+    }
+    // Sinks are opened one after another, so check them all before the first
+    // creates its files.
+    if mode == WriteMode::New {
+        sinks::refuse_existing(sinks.iter().flat_map(|sink| sink.paths()))?;
+    }
+    Ok(sinks)
+}
+
+/// Fill in the placeholders of an output path template, with the date and
+/// time the session started.
+pub(crate) fn expand_template(
+    template: &str,
+    source: &str,
+    device: &str,
+    started_at: DateTime<Utc>,
+) -> String {
+    let started = started_at.with_timezone(&Local);
+    // Only the last path component, so "/dev/ttyACM0" becomes "ttyACM0".
+    let port = Path::new(source)
+        .file_stem()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| source.to_string());
+
+    template
+        .replace("{date}", &started.format("%Y-%m-%d").to_string())
+        .replace("{time}", &started.format("%H%M%S").to_string())
+        .replace("{port}", &port)
+        .replace("{device}", device)
+}
@@ -0,0 +1,54 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+/// Column headers for the host timestamps written with every row.
+pub const HOST_TIME_HEADERS: [&str; 2] = ["Host Time", "Session Time (s)"];
+
+/// Host-side clock started when the recording session begins.
+#[derive(Debug, Clone, Copy)]
+pub struct SessionClock {
+    started: Instant,
+    started_at: DateTime<Utc>,
+}
+
+impl SessionClock {
+    pub fn start() -> Self {
+        SessionClock {
+            started: Instant::now(),
+            started_at: Utc::now(),
+        }
+    }
+
+    /// Wall-clock time the session started.
+    pub fn started_at(&self) -> DateTime<Utc> {
+        self.started_at
+    }
+
+    pub fn now(&self) -> HostTime {
+        HostTime {
+            wall: Utc::now(),
+            elapsed: self.started.elapsed(),
+        }
+    }
+}
+
+/// When the host received something.
+///
+/// `wall` follows the system clock and can jump if it's adjusted; `elapsed`
+/// comes from a monotonic clock and always increases over a session.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HostTime {
+    pub wall: DateTime<Utc>,
+    pub elapsed: Duration,
+}
+
+impl HostTime {
+    pub fn rfc3339(&self) -> String {
+        self.wall.to_rfc3339_opts(SecondsFormat::Millis, true)
+    }
+
+    pub fn session_seconds(&self) -> String {
+        format!("{:.3}", self.elapsed.as_secs_f64())
+    }
+}
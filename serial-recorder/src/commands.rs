@@ -0,0 +1,329 @@
+//! Commands sent to the device while recording, e.g. to configure it at the
+//! start of a session.
+//!
+//! A command is a line of text, optionally followed by `=>` and the start of
+//! the reply to wait for: `SET INTERVAL 5000 => OK`. A command with nothing
+//! before the `=>` just waits for a line, such as a greeting printed at boot.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use crate::events::{Event, EventKind};
+use crate::units::parse_duration;
+
+/// One line to send, and the reply expected for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    /// What to send, without a line ending. Empty to only wait for `reply`.
+    pub text: String,
+    /// The start of the line that answers this command.
+    pub reply: Option<String>,
+    /// How long to wait for the reply before giving up.
+    pub timeout: Duration,
+}
+
+impl Command {
+    /// Parse `TEXT [=> REPLY]`, waiting at most `timeout` for the reply.
+    pub fn parse(spec: &str, timeout: Duration) -> Result<Self, String> {
+        let (text, reply) = match spec.split_once("=>") {
+            Some((text, reply)) => (text.trim(), Some(reply.trim())),
+            None => (spec.trim(), None),
+        };
+        if reply.is_some_and(str::is_empty) {
+            return Err(format!("Command {:?} has nothing after =>", spec));
+        }
+        if text.is_empty() && reply.is_none() {
+            return Err("Empty command".to_string());
+        }
+        Ok(Command {
+            text: text.to_string(),
+            reply: reply.map(str::to_string),
+            timeout,
+        })
+    }
+}
+
+/// A step of a command script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Send(Command),
+    /// Pause before the next command.
+    Wait(Duration),
+}
+
+/// Read a command script: one command per line, with `@wait DURATION` to
+/// pause and `@timeout DURATION` to change the reply timeout for the lines
+/// after it. Blank lines and lines starting with `#` are ignored.
+pub fn read_script(path: &Path, timeout: Duration) -> Result<Vec<Step>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read script {}: {}", path.display(), e))?;
+
+    let mut timeout = timeout;
+    let mut steps = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = if let Some(duration) = line.strip_prefix("@wait") {
+            parse_duration(duration).map(|d| steps.push(Step::Wait(d)))
+        } else if let Some(duration) = line.strip_prefix("@timeout") {
+            parse_duration(duration).map(|d| timeout = d)
+        } else {
+            Command::parse(line, timeout).map(|command| steps.push(Step::Send(command)))
+        };
+        parsed.map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+    }
+    Ok(steps)
+}
+
+/// A command that's been sent and is waiting for its reply.
+#[derive(Debug)]
+struct Pending {
+    command: Command,
+    sent_at: Duration,
+}
+
+/// Queues commands, sends them one at a time and matches replies to them.
+///
+/// The next command isn't sent until the previous one has had its reply or
+/// timed out, so replies can't be mixed up.
+#[derive(Debug, Default)]
+pub struct CommandChannel {
+    queue: VecDeque<Step>,
+    live: Option<Receiver<Command>>,
+    pending: Option<Pending>,
+    /// Session time before which nothing is sent, after a `@wait`.
+    resume_at: Duration,
+}
+
+impl CommandChannel {
+    pub fn new(steps: Vec<Step>) -> Self {
+        CommandChannel {
+            queue: steps.into(),
+            ..CommandChannel::default()
+        }
+    }
+
+    /// Also send whatever is typed on standard input, one command per line.
+    pub fn read_stdin(&mut self, timeout: Duration) {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match Command::parse(&line, timeout) {
+                    Ok(command) => {
+                        if sender.send(command).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
+        });
+        self.live = Some(receiver);
+    }
+
+    /// The next command to send at session time `now`, if it's time for one.
+    /// Commands that only wait for a reply are started here too, and never
+    /// need sending.
+    pub fn next(&mut self, now: Duration) -> Option<Command> {
+        if let Some(live) = &self.live {
+            loop {
+                match live.try_recv() {
+                    Ok(command) => self.queue.push_back(Step::Send(command)),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.live = None;
+                        break;
+                    }
+                }
+            }
+        }
+
+        while self.pending.is_none() && now >= self.resume_at {
+            match self.queue.pop_front()? {
+                Step::Wait(duration) => self.resume_at = now + duration,
+                Step::Send(command) => {
+                    if command.reply.is_some() {
+                        self.pending = Some(Pending {
+                            command: command.clone(),
+                            sent_at: now,
+                        });
+                    }
+                    if !command.text.is_empty() {
+                        return Some(command);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// If `line` is the reply to the pending command, the event to record
+    /// for it. Replies aren't samples, so the caller shouldn't decode them.
+    pub fn reply(&mut self, line: &str, now: Duration) -> Option<Event> {
+        let pending = self.pending.as_ref()?;
+        let reply = pending.command.reply.as_deref()?;
+        if !line.trim().starts_with(reply) {
+            return None;
+        }
+        let pending = self.pending.take()?;
+        let waited = (now - pending.sent_at).as_secs_f64();
+        let detail = if pending.command.text.is_empty() {
+            format!("got {:?} after {:.2} s", line.trim(), waited)
+        } else {
+            format!(
+                "{:?} answered with {:?} after {:.2} s",
+                pending.command.text,
+                line.trim(),
+                waited
+            )
+        };
+        Some(Event::new(EventKind::CommandReply, detail))
+    }
+
+    /// An event for the pending command if its reply is overdue.
+    pub fn check_timeout(&mut self, now: Duration) -> Option<Event> {
+        let pending = self.pending.as_ref()?;
+        if now - pending.sent_at < pending.command.timeout {
+            return None;
+        }
+        let Pending { command, .. } = self.pending.take()?;
+        let reply = command.reply.unwrap_or_default();
+        let timeout = command.timeout.as_secs_f64();
+        let detail = if command.text.is_empty() {
+            format!("no line starting with {:?} within {:.1} s", reply, timeout)
+        } else {
+            format!(
+                "no reply starting with {:?} to {:?} within {:.1} s",
+                reply, command.text, timeout
+            )
+        };
+        Some(Event::new(EventKind::CommandTimeout, detail))
+    }
+
+    /// Give up on the pending command, e.g. because it couldn't be sent.
+    pub fn cancel(&mut self) {
+        self.pending = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    fn channel(specs: &[&str]) -> CommandChannel {
+        CommandChannel::new(
+            specs
+                .iter()
+                .map(|spec| Step::Send(Command::parse(spec, TIMEOUT).unwrap()))
+                .collect(),
+        )
+    }
+
+    /// A script in a file of its own.
+    fn script(name: &str, text: &str) -> Result<Vec<Step>, String> {
+        let path = std::env::temp_dir().join(format!(
+            "serial-recorder-script-{}-{}.txt",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, text).unwrap();
+        let steps = read_script(&path, TIMEOUT).map_err(|e| e.to_string());
+        fs::remove_file(&path).unwrap();
+        steps
+    }
+
+    #[test]
+    fn parses_commands() {
+        let command = Command::parse(" SET INTERVAL 5000 => OK ", TIMEOUT).unwrap();
+        assert_eq!(command.text, "SET INTERVAL 5000");
+        assert_eq!(command.reply.as_deref(), Some("OK"));
+        assert_eq!(Command::parse("=> READY", TIMEOUT).unwrap().text, "");
+        assert!(Command::parse("PING =>", TIMEOUT).is_err());
+        assert!(Command::parse("  ", TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn matches_the_reply_and_passes_other_lines_through() {
+        let mut commands = channel(&["SET INTERVAL 5000 => OK", "STATUS"]);
+        assert_eq!(commands.next(secs(0)).unwrap().text, "SET INTERVAL 5000");
+        // Nothing more is sent until the reply comes.
+        assert_eq!(commands.next(secs(0)), None);
+
+        assert_eq!(commands.reply("1000,72.5", secs(1)), None);
+        let event = commands.reply("OK 5000", secs(1)).unwrap();
+        assert_eq!(event.kind, EventKind::CommandReply);
+        assert!(event.detail.contains("\"OK 5000\""), "{}", event.detail);
+
+        assert_eq!(commands.next(secs(1)).unwrap().text, "STATUS");
+        // STATUS doesn't wait for anything, so no line is taken as its reply.
+        assert_eq!(commands.reply("OK", secs(2)), None);
+    }
+
+    #[test]
+    fn gives_up_on_an_overdue_reply() {
+        let mut commands = channel(&["PING => PONG", "STATUS"]);
+        commands.next(secs(10));
+        assert_eq!(commands.check_timeout(secs(11)), None);
+
+        let event = commands.check_timeout(secs(12)).unwrap();
+        assert_eq!(event.kind, EventKind::CommandTimeout);
+        assert!(event.detail.contains("\"PONG\""), "{}", event.detail);
+        // A late reply isn't matched to anything any more.
+        assert_eq!(commands.reply("PONG", secs(13)), None);
+        assert_eq!(commands.next(secs(13)).unwrap().text, "STATUS");
+    }
+
+    #[test]
+    fn waits_between_commands() {
+        let mut commands = CommandChannel::new(vec![
+            Step::Wait(secs(5)),
+            Step::Send(Command::parse("STATUS", TIMEOUT).unwrap()),
+        ]);
+        assert_eq!(commands.next(secs(0)), None);
+        assert_eq!(commands.next(secs(4)), None);
+        assert_eq!(commands.next(secs(5)).unwrap().text, "STATUS");
+    }
+
+    #[test]
+    fn reads_scripts() {
+        let steps = script(
+            "good",
+            "# Configure the sensor\n\nSET INTERVAL 5000 => OK\n@wait 1s\n@timeout 10s\nSTATUS => T=\n",
+        )
+        .unwrap();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[1], Step::Wait(secs(1)));
+        let Step::Send(status) = &steps[2] else {
+            panic!("expected a command, got {:?}", steps[2]);
+        };
+        assert_eq!(status.timeout, secs(10));
+
+        let error = script("bad", "STATUS\n@wait soon\n").unwrap_err();
+        assert!(
+            error.ends_with(":2: Invalid duration \"soon\", expected e.g. 30s or 5m"),
+            "{}",
+            error
+        );
+        assert!(script("empty-reply", "PING =>\n").is_err());
+    }
+}
@@ -0,0 +1,570 @@
+//! Full-screen live view of a running recording.
+//!
+//! The dashboard is just another [`Sink`]: it keeps a rolling history of the
+//! rows and events it's given, and a background thread redraws the terminal a
+//! few times a second from that history.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use ratatui::crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph, Row, Sparkline, Table};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::clock::HostTime;
+use crate::decoder::Value;
+use crate::events::{Event, EventCounts, EventKind};
+use crate::sinks::{SessionInfo, Sink};
+
+/// How often the screen is redrawn, and how long a key press can go unseen.
+const REFRESH: Duration = Duration::from_millis(250);
+
+/// Samples per second are averaged over this long.
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Points kept for the sparkline regardless of the statistics windows.
+const CHART_POINTS: usize = 512;
+
+/// Upper bound on the history kept per channel, whatever the windows are.
+const MAX_HISTORY: usize = 100_000;
+
+/// How many of the latest events are listed.
+const RECENT_EVENTS: usize = 5;
+
+/// Live terminal view of the latest values, rolling statistics, sample rate,
+/// event counts and connection state.
+///
+/// Pressing `q`, Esc or Ctrl-C sets `stop`, which the [`crate::Recorder`]
+/// checks between reads; Tab and the arrow keys pick the charted channel.
+pub struct Dashboard {
+    windows: Vec<Duration>,
+    chart_column: usize,
+    stop: Arc<AtomicBool>,
+    done: Arc<AtomicBool>,
+    state: Arc<Mutex<State>>,
+    ui: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Dashboard {
+    /// Show min/max/mean over each of `windows` and chart the values in
+    /// column `chart_column`, setting `stop` when the user quits.
+    pub fn new(windows: Vec<Duration>, chart_column: usize, stop: Arc<AtomicBool>) -> Self {
+        Dashboard {
+            windows,
+            chart_column,
+            stop,
+            done: Arc::new(AtomicBool::new(false)),
+            state: Arc::new(Mutex::new(State::default())),
+            ui: None,
+        }
+    }
+}
+
+impl Sink for Dashboard {
+    fn open(&mut self, session: &SessionInfo) -> Result<(), Box<dyn Error>> {
+        {
+            let mut state = lock(&self.state);
+            state.device = session.device.clone();
+            state.source = session.source.clone();
+            state.windows = self.windows.clone();
+            state.opened = Some(Instant::now());
+            state.channels = session
+                .headers
+                .iter()
+                .enumerate()
+                .map(|(column, name)| Channel {
+                    name: name.clone(),
+                    column,
+                    history: VecDeque::new(),
+                })
+                .collect();
+            state.selected = self
+                .chart_column
+                .min(state.channels.len().saturating_sub(1));
+        }
+
+        let terminal = ratatui::try_init()?;
+        let state = Arc::clone(&self.state);
+        let stop = Arc::clone(&self.stop);
+        let done = Arc::clone(&self.done);
+        self.ui = Some(thread::spawn(move || {
+            let result = run_ui(terminal, &state, &stop, &done);
+            ratatui::try_restore()?;
+            result
+        }));
+        Ok(())
+    }
+
+    fn write_sample(
+        &mut self,
+        received: &HostTime,
+        values: &[Value],
+    ) -> Result<(), Box<dyn Error>> {
+        lock(&self.state).add_sample(received.elapsed, values);
+        Ok(())
+    }
+
+    fn write_event(&mut self, received: &HostTime, event: &Event) -> Result<(), Box<dyn Error>> {
+        lock(&self.state).add_event(received.elapsed, event);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(ui) = self.ui.take() else {
+            return Ok(());
+        };
+        self.done.store(true, Ordering::Relaxed);
+        ui.join().map_err(|_| "Dashboard thread panicked")??;
+        Ok(())
+    }
+}
+
+impl Drop for Dashboard {
+    // Give the terminal back even if the recording ended with an error.
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().expect("Dashboard state poisoned")
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+enum Connection {
+    /// Nothing has arrived yet.
+    #[default]
+    Waiting,
+    Connected,
+    Lost {
+        since: Duration,
+        reason: String,
+    },
+}
+
+/// Recent values of one column.
+struct Channel {
+    name: String,
+    column: usize,
+    /// Session time and value of each sample, oldest first. Text values are
+    /// skipped, so this stays empty for text columns.
+    history: VecDeque<(Duration, f64)>,
+}
+
+impl Channel {
+    fn latest(&self) -> Option<f64> {
+        self.history.back().map(|&(_, value)| value)
+    }
+
+    /// Min, max and mean of the values received since `since`.
+    fn stats_since(&self, since: Duration) -> Option<(f64, f64, f64)> {
+        let recent = self
+            .history
+            .iter()
+            .rev()
+            .take_while(|&&(at, _)| at >= since)
+            .map(|&(_, value)| value);
+
+        let (mut min, mut max, mut sum, mut count) = (f64::INFINITY, f64::NEG_INFINITY, 0.0, 0);
+        for value in recent {
+            min = min.min(value);
+            max = max.max(value);
+            sum += value;
+            count += 1;
+        }
+        (count > 0).then(|| (min, max, sum / count as f64))
+    }
+}
+
+#[derive(Default)]
+struct State {
+    device: String,
+    source: String,
+    windows: Vec<Duration>,
+    opened: Option<Instant>,
+    channels: Vec<Channel>,
+    selected: usize,
+    connection: Connection,
+    last_line: Option<Duration>,
+    /// Session times of the samples within [`RATE_WINDOW`].
+    recent_samples: VecDeque<Duration>,
+    events: EventCounts,
+    recent_events: VecDeque<(Duration, Event)>,
+    stopping: bool,
+}
+
+impl State {
+    /// Time since the dashboard was opened, on the same footing as the
+    /// session times of the rows.
+    fn now(&self) -> Duration {
+        self.opened
+            .map(|opened| opened.elapsed())
+            .unwrap_or_default()
+    }
+
+    fn add_sample(&mut self, at: Duration, values: &[Value]) {
+        self.events.count_sample();
+        self.last_line = Some(at);
+        if self.connection == Connection::Waiting {
+            self.connection = Connection::Connected;
+        }
+
+        let longest = self.windows.iter().max().copied().unwrap_or_default();
+        for channel in self.channels.iter_mut() {
+            let Some(value) = values.get(channel.column).and_then(Value::as_f64) else {
+                continue;
+            };
+            channel.history.push_back((at, value));
+            while channel.history.len() > MAX_HISTORY
+                || (channel.history.len() > CHART_POINTS
+                    && channel
+                        .history
+                        .front()
+                        .is_some_and(|&(t, _)| t + longest < at))
+            {
+                channel.history.pop_front();
+            }
+        }
+
+        self.recent_samples.push_back(at);
+        while self
+            .recent_samples
+            .front()
+            .is_some_and(|&t| t + RATE_WINDOW < at)
+        {
+            self.recent_samples.pop_front();
+        }
+    }
+
+    fn add_event(&mut self, at: Duration, event: &Event) {
+        self.events.record(event.kind);
+        match event.kind {
+            EventKind::Disconnected => {
+                self.connection = Connection::Lost {
+                    since: at,
+                    reason: event.detail.clone(),
+                }
+            }
+            EventKind::Reconnected => self.connection = Connection::Connected,
+            _ => {
+                self.last_line = Some(at);
+                if self.connection == Connection::Waiting {
+                    self.connection = Connection::Connected;
+                }
+            }
+        }
+
+        if self.recent_events.len() == RECENT_EVENTS {
+            self.recent_events.pop_front();
+        }
+        self.recent_events.push_back((at, event.clone()));
+    }
+
+    /// Samples per second over the last [`RATE_WINDOW`], from the spacing of
+    /// the samples themselves so a fresh session doesn't show a spike.
+    fn sample_rate(&self, now: Duration) -> f64 {
+        let mut recent = self
+            .recent_samples
+            .iter()
+            .filter(|&&t| t + RATE_WINDOW >= now);
+        let (Some(first), Some(last)) = (recent.next(), recent.next_back()) else {
+            return 0.0;
+        };
+        let span = (*last - *first).as_secs_f64();
+        if span > 0.0 {
+            (recent.count() + 1) as f64 / span
+        } else {
+            0.0
+        }
+    }
+
+    /// Channels that have had at least one numeric value.
+    fn numeric_channels(&self) -> Vec<usize> {
+        (0..self.channels.len())
+            .filter(|&i| !self.channels[i].history.is_empty())
+            .collect()
+    }
+
+    /// Move the chart `step` numeric channels along, wrapping around.
+    fn select(&mut self, step: isize) {
+        let numeric = self.numeric_channels();
+        if numeric.is_empty() {
+            return;
+        }
+        let current = numeric
+            .iter()
+            .position(|&i| i == self.selected)
+            .unwrap_or(0) as isize;
+        let next = (current + step).rem_euclid(numeric.len() as isize);
+        self.selected = numeric[next as usize];
+    }
+}
+
+fn run_ui(
+    mut terminal: DefaultTerminal,
+    state: &Mutex<State>,
+    stop: &AtomicBool,
+    done: &AtomicBool,
+) -> io::Result<()> {
+    while !done.load(Ordering::Relaxed) {
+        terminal.draw(|frame| draw(frame, &lock(state)))?;
+
+        if !event::poll(REFRESH)? {
+            continue;
+        }
+        let TermEvent::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        // Raw mode swallows Ctrl-C, so it has to be handled here.
+        let ctrl_c =
+            key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => quit(state, stop),
+            _ if ctrl_c => quit(state, stop),
+            KeyCode::Tab | KeyCode::Right => lock(state).select(1),
+            KeyCode::BackTab | KeyCode::Left => lock(state).select(-1),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn quit(state: &Mutex<State>, stop: &AtomicBool) {
+    lock(state).stopping = true;
+    stop.store(true, Ordering::Relaxed);
+}
+
+fn draw(frame: &mut Frame, state: &State) {
+    let now = state.now();
+    let [status, table, chart, events, footer] = Layout::vertical([
+        Constraint::Length(4),
+        Constraint::Length(state.channels.len() as u16 + 3),
+        Constraint::Min(6),
+        Constraint::Length(RECENT_EVENTS as u16 + 3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    draw_status(frame, status, state, now);
+    draw_table(frame, table, state, now);
+    draw_chart(frame, chart, state);
+    draw_events(frame, events, state);
+
+    let help = if state.stopping {
+        "Stopping, closing the output files..."
+    } else {
+        "q quit   Tab/←/→ chart another channel"
+    };
+    frame.render_widget(
+        Paragraph::new(help).style(Style::default().add_modifier(Modifier::DIM)),
+        footer,
+    );
+}
+
+fn draw_status(frame: &mut Frame, area: Rect, state: &State, now: Duration) {
+    let connection = match &state.connection {
+        Connection::Waiting => Span::styled("waiting for data", Style::default().fg(Color::Yellow)),
+        Connection::Connected => Span::styled("connected", Style::default().fg(Color::Green)),
+        Connection::Lost { since, reason } => Span::styled(
+            format!(
+                "disconnected for {} ({})",
+                format_elapsed(now.saturating_sub(*since)),
+                reason
+            ),
+            Style::default().fg(Color::Red),
+        ),
+    };
+    let last_line = match state.last_line {
+        Some(at) => format!(
+            "last line {:.1} s ago",
+            now.saturating_sub(at).as_secs_f64()
+        ),
+        None => "no lines yet".to_string(),
+    };
+
+    let lines = vec![
+        Line::from(vec![
+            connection,
+            Span::raw(format!(
+                "   {}   running {}   {:.2} samples/s",
+                last_line,
+                format_elapsed(now),
+                state.sample_rate(now)
+            )),
+        ]),
+        Line::from(state.events.summary()),
+    ];
+    let title = format!(" {} — {} ", state.device, state.source);
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        area,
+    );
+}
+
+fn draw_table(frame: &mut Frame, area: Rect, state: &State, now: Duration) {
+    let mut header = vec!["Channel".to_string(), "Latest".to_string()];
+    header.extend(
+        state
+            .windows
+            .iter()
+            .map(|window| format!("min / max / mean ({})", format_window(*window))),
+    );
+
+    let rows = state.channels.iter().enumerate().map(|(i, channel)| {
+        let mut cells = vec![
+            channel.name.clone(),
+            channel
+                .latest()
+                .map(format_value)
+                .unwrap_or_else(|| "-".into()),
+        ];
+        for window in &state.windows {
+            let since = now.saturating_sub(*window);
+            cells.push(match channel.stats_since(since) {
+                Some((min, max, mean)) => format!(
+                    "{} / {} / {}",
+                    format_value(min),
+                    format_value(max),
+                    format_value(mean)
+                ),
+                None => "-".to_string(),
+            });
+        }
+        let row = Row::new(cells);
+        if i == state.selected {
+            row.style(Style::default().add_modifier(Modifier::BOLD))
+        } else {
+            row
+        }
+    });
+
+    let name_width = state
+        .channels
+        .iter()
+        .map(|channel| channel.name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("Channel".len()) as u16;
+    let mut widths = vec![Constraint::Length(name_width), Constraint::Length(12)];
+    widths.extend(state.windows.iter().map(|_| Constraint::Min(24)));
+
+    let table = Table::new(rows, widths)
+        .header(Row::new(header).style(Style::default().add_modifier(Modifier::UNDERLINED)))
+        .block(Block::bordered().title(" Channels "));
+    frame.render_widget(table, area);
+}
+
+fn draw_chart(frame: &mut Frame, area: Rect, state: &State) {
+    let Some(channel) = state.channels.get(state.selected) else {
+        frame.render_widget(Block::bordered().title(" Chart "), area);
+        return;
+    };
+
+    // One bar per column, newest on the right.
+    let width = area.width.saturating_sub(2) as usize;
+    let points: Vec<f64> = channel
+        .history
+        .iter()
+        .rev()
+        .take(width)
+        .map(|&(_, value)| value)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+
+    let min = points.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = points.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    // Bars are scaled between the lowest and highest value shown, leaving
+    // the lowest one visible.
+    let bars: Vec<u64> = points
+        .iter()
+        .map(|&value| {
+            if max > min {
+                1 + ((value - min) / (max - min) * 99.0).round() as u64
+            } else {
+                50
+            }
+        })
+        .collect();
+
+    let title = if points.is_empty() {
+        format!(" {} ", channel.name)
+    } else {
+        format!(
+            " {}: last {} samples, {} to {} ",
+            channel.name,
+            points.len(),
+            format_value(min),
+            format_value(max)
+        )
+    };
+    let sparkline = Sparkline::default()
+        .block(Block::bordered().title(title))
+        .data(&bars)
+        .max(100)
+        .style(Style::default().fg(Color::Cyan));
+    frame.render_widget(sparkline, area);
+}
+
+fn draw_events(frame: &mut Frame, area: Rect, state: &State) {
+    let lines: Vec<Line> = state
+        .recent_events
+        .iter()
+        .rev()
+        .map(|(at, event)| {
+            let style = match event.kind {
+                EventKind::Disconnected => Style::default().fg(Color::Red),
+                EventKind::Reconnected => Style::default().fg(Color::Green),
+                _ => Style::default().fg(Color::Yellow),
+            };
+            Line::from(vec![
+                Span::raw(format!("{:>10}  ", format_elapsed(*at))),
+                Span::styled(format!("{:<18}", event.kind.name()), style),
+                Span::raw(event.detail.clone()),
+            ])
+        })
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Recent events ")),
+        area,
+    );
+}
+
+/// Whole numbers without decimals, e.g. RPM; everything else to two places.
+fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+/// "1:02:03" style session time.
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// "10s", "5m" or "1h" style window length.
+fn format_window(window: Duration) -> String {
+    let secs = window.as_secs();
+    if secs > 0 && secs.is_multiple_of(3600) {
+        format!("{}h", secs / 3600)
+    } else if secs > 0 && secs.is_multiple_of(60) {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}s", window.as_secs_f64())
+    }
+}
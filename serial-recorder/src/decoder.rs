@@ -0,0 +1,123 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::events::EventKind;
+
+/// A single parsed field from a device line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    U32(u32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Text(String),
+}
+
+impl Value {
+    /// The value as a number, or `None` for text.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::U32(v) => Some(*v as f64),
+            Value::U64(v) => Some(*v as f64),
+            Value::I64(v) => Some(*v as f64),
+            Value::F32(v) => Some(widen(*v)),
+            Value::F64(v) => Some(*v),
+            Value::Text(_) => None,
+        }
+    }
+}
+
+/// Floats are stored as `f32`; going through their shortest decimal form keeps
+/// e.g. 72.3 from turning into 72.30000305175781 when widened.
+pub(crate) fn widen(value: f32) -> f64 {
+    value.to_string().parse().unwrap_or(value as f64)
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::U32(v) => write!(f, "{}", v),
+            Value::U64(v) => write!(f, "{}", v),
+            Value::I64(v) => write!(f, "{}", v),
+            Value::F32(v) => write!(f, "{}", v),
+            Value::F64(v) => write!(f, "{}", v),
+            Value::Text(v) => f.write_str(v),
+        }
+    }
+}
+
+/// Returned by a decoder for any line that isn't a sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodeError {
+    pub kind: EventKind,
+    pub line: String,
+    /// Why the line was rejected, when the decoder can say more than that it
+    /// didn't match.
+    pub reason: Option<String>,
+}
+
+impl DecodeError {
+    /// A line that doesn't match the device's format.
+    pub fn new(line: &str) -> Self {
+        DecodeError::with_kind(EventKind::Unparsed, line)
+    }
+
+    /// A line the decoder recognised as something other than a sample, such as
+    /// an error message from the firmware.
+    pub fn with_kind(kind: EventKind, line: &str) -> Self {
+        DecodeError {
+            kind,
+            line: line.to_string(),
+            reason: None,
+        }
+    }
+
+    /// A line that doesn't match the device's format, and why.
+    pub fn invalid(line: &str, reason: impl Into<String>) -> Self {
+        DecodeError {
+            reason: Some(reason.into()),
+            ..DecodeError::new(line)
+        }
+    }
+
+    /// The line, followed by the reason if there is one, for event output.
+    pub fn detail(&self) -> String {
+        match &self.reason {
+            Some(reason) => format!("{} ({})", self.line, reason),
+            None => self.line.clone(),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.kind, &self.reason) {
+            (EventKind::Unparsed, Some(reason)) => {
+                write!(f, "Failed to parse line {:?}: {}", self.line, reason)
+            }
+            (EventKind::Unparsed, None) => write!(f, "Failed to parse line {:?}", self.line),
+            (kind, _) => write!(f, "Device reported {}: {:?}", kind, self.line),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Turns one line of device output into a row of values.
+///
+/// Implement this for each instrument; the read loop, line splitting and CSV
+/// output are handled by [`crate::Recorder`].
+pub trait LineDecoder {
+    /// Column headers, one per value returned by [`LineDecoder::decode`].
+    fn headers(&self) -> Vec<String>;
+
+    /// Parse a single line. The line terminator has already been removed.
+    ///
+    /// Lines that aren't samples are returned as errors, tagged with the kind
+    /// of event they represent.
+    fn decode(&mut self, line: &str) -> Result<Vec<Value>, DecodeError>;
+
+    /// Text printed to standard output for each decoded row.
+    fn describe(&self, values: &[Value]) -> String;
+}
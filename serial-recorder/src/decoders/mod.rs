@@ -0,0 +1,10 @@
+//! Decoders for the instruments in this repository, and a generic one for
+//! anything else that prints delimited values.
+
+mod rpm;
+mod schema;
+mod temperature;
+
+pub use rpm::RpmDecoder;
+pub use schema::{parse_delimiter, Field, FieldType, Schema, SchemaDecoder};
+pub use temperature::TemperatureDecoder;
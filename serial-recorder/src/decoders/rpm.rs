@@ -0,0 +1,23 @@
+use crate::decoder::{DecodeError, LineDecoder, Value};
+
+/// Decoder for tacho.ino, which prints one bare RPM integer per line.
+#[derive(Debug, Default)]
+pub struct RpmDecoder;
+
+impl LineDecoder for RpmDecoder {
+    fn headers(&self) -> Vec<String> {
+        vec!["RPM".to_string()]
+    }
+
+    fn decode(&mut self, line: &str) -> Result<Vec<Value>, DecodeError> {
+        let rpm = line
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| DecodeError::new(line))?;
+        Ok(vec![Value::U32(rpm)])
+    }
+
+    fn describe(&self, values: &[Value]) -> String {
+        format!("RPM: {}", values[0])
+    }
+}
@@ -0,0 +1,354 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::decoder::{DecodeError, LineDecoder, Value};
+
+/// The type of one field in a [`Schema`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    Str,
+}
+
+impl FieldType {
+    const NAMES: [(&'static str, FieldType); 11] = [
+        ("u8", FieldType::U8),
+        ("u16", FieldType::U16),
+        ("u32", FieldType::U32),
+        ("u64", FieldType::U64),
+        ("i8", FieldType::I8),
+        ("i16", FieldType::I16),
+        ("i32", FieldType::I32),
+        ("i64", FieldType::I64),
+        ("f32", FieldType::F32),
+        ("f64", FieldType::F64),
+        ("str", FieldType::Str),
+    ];
+
+    /// Parse one field of a line. Narrow integers are range checked and then
+    /// stored in the smallest [`Value`] that holds them.
+    pub fn parse(&self, text: &str) -> Option<Value> {
+        Some(match self {
+            FieldType::U8 => Value::U32(text.parse::<u8>().ok()? as u32),
+            FieldType::U16 => Value::U32(text.parse::<u16>().ok()? as u32),
+            FieldType::U32 => Value::U32(text.parse().ok()?),
+            FieldType::U64 => Value::U64(text.parse().ok()?),
+            FieldType::I8 => Value::I64(text.parse::<i8>().ok()? as i64),
+            FieldType::I16 => Value::I64(text.parse::<i16>().ok()? as i64),
+            FieldType::I32 => Value::I64(text.parse::<i32>().ok()? as i64),
+            FieldType::I64 => Value::I64(text.parse().ok()?),
+            FieldType::F32 => Value::F32(text.parse().ok()?),
+            FieldType::F64 => Value::F64(text.parse().ok()?),
+            FieldType::Str => Value::Text(text.to_string()),
+        })
+    }
+}
+
+impl FromStr for FieldType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FieldType::NAMES
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|&(_, kind)| kind)
+            .ok_or_else(|| {
+                let names: Vec<&str> = FieldType::NAMES.iter().map(|(name, _)| *name).collect();
+                format!("Unknown type {:?}, expected one of {}", s, names.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _) = FieldType::NAMES
+            .iter()
+            .find(|(_, kind)| kind == self)
+            .expect("Every type has a name.");
+        f.write_str(name)
+    }
+}
+
+/// A named, typed field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    pub kind: FieldType,
+}
+
+/// The layout of a delimited line, e.g. `ts:u32,temp_f:f32,humidity:u8`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schema {
+    pub delimiter: char,
+    pub fields: Vec<Field>,
+}
+
+impl Schema {
+    /// Parse comma-separated `name:type` fields, for lines whose fields are
+    /// separated by `delimiter`.
+    pub fn parse(spec: &str, delimiter: char) -> Result<Self, String> {
+        let fields = spec
+            .split(',')
+            .map(parse_field)
+            .collect::<Result<Vec<_>, _>>()?;
+        Schema::new(delimiter, fields)
+    }
+
+    /// Read a schema file: one `name: type` field per line, in order, and
+    /// optionally a `delimiter = ;` line. Blank lines and lines starting with
+    /// `#` are ignored.
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read schema {}: {}", path.display(), e))?;
+
+        let mut delimiter = ',';
+        let mut fields = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parsed = match line.split_once('=') {
+                Some((key, value)) if key.trim() == "delimiter" => {
+                    parse_delimiter(value.trim()).map(|d| delimiter = d)
+                }
+                _ => parse_field(line).map(|field| fields.push(field)),
+            };
+            parsed.map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+        }
+        Ok(Schema::new(delimiter, fields)?)
+    }
+
+    fn new(delimiter: char, fields: Vec<Field>) -> Result<Self, String> {
+        if fields.is_empty() {
+            return Err("The schema has no fields".to_string());
+        }
+        for (i, field) in fields.iter().enumerate() {
+            if fields[..i].iter().any(|other| other.name == field.name) {
+                return Err(format!(
+                    "Field {:?} appears twice in the schema",
+                    field.name
+                ));
+            }
+        }
+        Ok(Schema { delimiter, fields })
+    }
+}
+
+fn parse_field(spec: &str) -> Result<Field, String> {
+    let (name, kind) = spec
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid field {:?}, expected name:type", spec.trim()))?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(format!("Field {:?} has no name", spec.trim()));
+    }
+    Ok(Field {
+        name: name.to_string(),
+        kind: kind.trim().parse()?,
+    })
+}
+
+/// A single character, or `tab` or `space`.
+pub fn parse_delimiter(text: &str) -> Result<char, String> {
+    match text {
+        "tab" | "\\t" => Ok('\t'),
+        "space" => Ok(' '),
+        _ => {
+            let mut chars = text.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(c),
+                _ => Err(format!(
+                    "Invalid delimiter {:?}, expected a single character, tab or space",
+                    text
+                )),
+            }
+        }
+    }
+}
+
+/// Decodes lines laid out by a [`Schema`], for sketches that print delimited
+/// values and don't need a decoder of their own.
+#[derive(Debug, Clone)]
+pub struct SchemaDecoder {
+    schema: Schema,
+}
+
+impl SchemaDecoder {
+    pub fn new(schema: Schema) -> Self {
+        SchemaDecoder { schema }
+    }
+}
+
+impl LineDecoder for SchemaDecoder {
+    fn headers(&self) -> Vec<String> {
+        self.schema
+            .fields
+            .iter()
+            .map(|field| field.name.clone())
+            .collect()
+    }
+
+    fn decode(&mut self, line: &str) -> Result<Vec<Value>, DecodeError> {
+        let trimmed = line.trim();
+        // Runs of spaces are a single separator when lining up columns.
+        let parts: Vec<&str> = if self.schema.delimiter == ' ' {
+            trimmed.split_whitespace().collect()
+        } else {
+            trimmed.split(self.schema.delimiter).collect()
+        };
+
+        let fields = &self.schema.fields;
+        if parts.len() != fields.len() {
+            return Err(DecodeError::invalid(
+                line,
+                format!("expected {} fields, got {}", fields.len(), parts.len()),
+            ));
+        }
+
+        fields
+            .iter()
+            .zip(parts)
+            .map(|(field, text)| {
+                field.kind.parse(text.trim()).ok_or_else(|| {
+                    DecodeError::invalid(
+                        line,
+                        format!(
+                            "{:?} is not a valid {} for {}",
+                            text, field.kind, field.name
+                        ),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn describe(&self, values: &[Value]) -> String {
+        self.schema
+            .fields
+            .iter()
+            .zip(values)
+            .map(|(field, value)| format!("{}: {}", field.name, value))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+
+    fn schema_decoder(spec: &str, delimiter: char) -> SchemaDecoder {
+        SchemaDecoder::new(Schema::parse(spec, delimiter).unwrap())
+    }
+
+    /// Why a line was rejected.
+    fn rejected(decoder: &mut SchemaDecoder, line: &str) -> String {
+        let error = decoder.decode(line).unwrap_err();
+        assert_eq!(error.kind, EventKind::Unparsed);
+        error.reason.unwrap_or_default()
+    }
+
+    #[test]
+    fn parses_schemas() {
+        let schema = Schema::parse("ts:u32, temp_f : f32,label:str", ';').unwrap();
+        assert_eq!(schema.delimiter, ';');
+        assert_eq!(
+            schema.fields,
+            [
+                Field {
+                    name: "ts".to_string(),
+                    kind: FieldType::U32,
+                },
+                Field {
+                    name: "temp_f".to_string(),
+                    kind: FieldType::F32,
+                },
+                Field {
+                    name: "label".to_string(),
+                    kind: FieldType::Str,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_schemas() {
+        let unknown = Schema::parse("ts:u32,temp_f:float", ',').unwrap_err();
+        assert!(unknown.starts_with("Unknown type \"float\""), "{}", unknown);
+        assert!(Schema::parse("ts:u32,temp_f", ',').is_err());
+        assert!(Schema::parse(":u32", ',').is_err());
+        assert!(Schema::parse("ts:u32,ts:u64", ',').is_err());
+        assert!(Schema::parse("", ',').is_err());
+    }
+
+    #[test]
+    fn parses_delimiters() {
+        assert_eq!(parse_delimiter(";"), Ok(';'));
+        assert_eq!(parse_delimiter("tab"), Ok('\t'));
+        assert_eq!(parse_delimiter("\\t"), Ok('\t'));
+        assert_eq!(parse_delimiter("space"), Ok(' '));
+        assert!(parse_delimiter("").is_err());
+        assert!(parse_delimiter("::").is_err());
+    }
+
+    #[test]
+    fn decodes_lines() {
+        let mut decoder = schema_decoder("ts:u32,temp_f:f32,humidity:u8,offset:i16", ',');
+        assert_eq!(
+            decoder.decode("1000, 72.5,45,-3\r\n"),
+            Ok(vec![
+                Value::U32(1000),
+                Value::F32(72.5),
+                Value::U32(45),
+                Value::I64(-3),
+            ])
+        );
+
+        let mut decoder = schema_decoder("ts:u32,state:str", ' ');
+        assert_eq!(
+            decoder.decode("1000   idle"),
+            Ok(vec![Value::U32(1000), Value::Text("idle".to_string())])
+        );
+    }
+
+    #[test]
+    fn rejects_the_wrong_number_of_fields() {
+        let mut decoder = schema_decoder("ts:u32,temp_f:f32", ',');
+        assert_eq!(rejected(&mut decoder, "1000"), "expected 2 fields, got 1");
+        assert_eq!(
+            rejected(&mut decoder, "1000,72.5,45"),
+            "expected 2 fields, got 3"
+        );
+    }
+
+    #[test]
+    fn rejects_values_of_the_wrong_type() {
+        let mut decoder = schema_decoder("ts:u32,humidity:u8,temp_f:f32", ',');
+        assert_eq!(
+            rejected(&mut decoder, "1000,300,72.5"),
+            "\"300\" is not a valid u8 for humidity"
+        );
+        assert_eq!(
+            rejected(&mut decoder, "-1,45,72.5"),
+            "\"-1\" is not a valid u32 for ts"
+        );
+        assert_eq!(
+            rejected(&mut decoder, "1000,45,warm"),
+            "\"warm\" is not a valid f32 for temp_f"
+        );
+    }
+}
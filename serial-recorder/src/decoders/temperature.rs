@@ -0,0 +1,98 @@
+use crate::decoder::{DecodeError, LineDecoder, Value};
+use crate::events::EventKind;
+
+/// Decoder for temp-monitor, which prints `<timestamp>,<temperature>` lines.
+#[derive(Debug, Default)]
+pub struct TemperatureDecoder;
+
+impl LineDecoder for TemperatureDecoder {
+    fn headers(&self) -> Vec<String> {
+        vec!["Timestamp (ms)".to_string(), "Temperature (°F)".to_string()]
+    }
+
+    fn decode(&mut self, line: &str) -> Result<Vec<Value>, DecodeError> {
+        // Messages printed when Sensor::perform_measurement fails.
+        match line.trim() {
+            "Pin Error!" => return Err(DecodeError::with_kind(EventKind::PinError, line)),
+            "Checksum Mismatch!" => {
+                return Err(DecodeError::with_kind(EventKind::ChecksumMismatch, line))
+            }
+            "Timeout!" => return Err(DecodeError::with_kind(EventKind::Timeout, line)),
+            _ => (),
+        }
+
+        let (timestamp_str, temperature_str) =
+            parse_data(line).ok_or_else(|| DecodeError::new(line))?;
+        let timestamp = timestamp_str
+            .parse::<u32>()
+            .map_err(|_| DecodeError::new(line))?;
+        let temperature = temperature_str
+            .trim()
+            .parse::<f32>()
+            .map_err(|_| DecodeError::new(line))?;
+        Ok(vec![Value::U32(timestamp), Value::F32(temperature)])
+    }
+
+    fn describe(&self, values: &[Value]) -> String {
+        match values {
+            [timestamp, Value::F32(temperature)] => {
+                format!(
+                    "Timestamp: {} ms, Temperature: {:.2}°F",
+                    timestamp, temperature
+                )
+            }
+            _ => format!("{:?}", values),
+        }
+    }
+}
+
+fn parse_data(line: &str) -> Option<(&str, &str)> {
+    let parts: Vec<&str> = line.trim().split(',').collect();
+    if parts.len() == 2 {
+        Some((parts[0], parts[1]))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The event a line is recorded as, or `None` if it's a sample.
+    fn kind(line: &str) -> Option<EventKind> {
+        TemperatureDecoder
+            .decode(line)
+            .err()
+            .map(|error| error.kind)
+    }
+
+    #[test]
+    fn decodes_samples() {
+        assert_eq!(
+            TemperatureDecoder.decode("12000,72.50\r\n"),
+            Ok(vec![Value::U32(12000), Value::F32(72.5)])
+        );
+    }
+
+    #[test]
+    fn recognises_firmware_errors() {
+        assert_eq!(kind("Pin Error!\r\n"), Some(EventKind::PinError));
+        assert_eq!(
+            kind("Checksum Mismatch!\r\n"),
+            Some(EventKind::ChecksumMismatch)
+        );
+        assert_eq!(kind("Timeout!\r\n"), Some(EventKind::Timeout));
+
+        let error = TemperatureDecoder.decode("Timeout!").unwrap_err();
+        assert_eq!(error.line, "Timeout!");
+        assert_eq!(error.reason, None);
+    }
+
+    #[test]
+    fn leaves_other_lines_unparsed() {
+        for line in ["Sensor ready", "timeout!", "12000", "12000,warm", "-5,72.5"] {
+            assert_eq!(kind(line), Some(EventKind::Unparsed), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use crate::clock::HostTime;
+use crate::decoder::Value;
+use crate::events::{Event, EventKind};
+use crate::stage::Stage;
+
+/// Header of the device epoch column, which counts resets.
+pub const EPOCH_HEADER: &str = "Device Epoch";
+
+/// Header of the unwrapped device time column.
+pub const TIME_HEADER: &str = "Unwrapped Time (ms)";
+
+/// How far the counter and the host may disagree, in milliseconds, before
+/// a step is taken for a reset.
+const SLACK_MS: u64 = 60_000;
+
+/// Turns a device's u32 millisecond counter into a continuous session time.
+///
+/// The counter restarts at zero whenever the board resets (including the DTR
+/// toggle when the port is opened) and wraps after ~49 days. A backwards step
+/// whose forward distance matches the time the host saw pass is a wrap and is
+/// carried into the upper bits; anything else is a reset, which starts a new
+/// device epoch and bridges the gap using host time. So is a forward step far
+/// shorter than the host gap, as when the board restarted during a long
+/// disconnect and its counter has already passed the last value seen.
+pub struct DeviceClock {
+    column: usize,
+    last: Option<Last>,
+    epoch: u32,
+}
+
+struct Last {
+    raw: u32,
+    unwrapped: u64,
+    received: HostTime,
+}
+
+impl DeviceClock {
+    /// `column` is the index of the device timestamp among the decoded values.
+    pub fn new(column: usize) -> Self {
+        DeviceClock {
+            column,
+            last: None,
+            epoch: 0,
+        }
+    }
+
+    /// Work out the unwrapped time for `raw`, noting any reset or wrap.
+    fn unwrap(&mut self, raw: u32, received: &HostTime) -> (u64, Option<Event>) {
+        let Some(last) = &self.last else {
+            return (raw as u64, None);
+        };
+
+        let forward = raw.wrapping_sub(last.raw) as u64;
+        let host_gap = received.elapsed.saturating_sub(last.received.elapsed);
+        let host_gap_ms = host_gap.as_millis() as u64;
+
+        // Allow for plenty of drift and scheduling jitter either way, but a
+        // reset lands far from what the host measured.
+        if raw >= last.raw {
+            if host_gap_ms <= 2 * forward + SLACK_MS {
+                return (last.unwrapped + forward, None);
+            }
+        } else if forward <= 2 * host_gap_ms + SLACK_MS {
+            let event = Event::new(
+                EventKind::CounterWrap,
+                format!("device counter wrapped from {} to {}", last.raw, raw),
+            );
+            return (last.unwrapped + forward, Some(event));
+        }
+
+        self.epoch += 1;
+        let change = if raw >= last.raw {
+            format!(
+                "only went from {} to {} in {:.1} s",
+                last.raw,
+                raw,
+                host_gap.as_secs_f64()
+            )
+        } else {
+            format!("went back from {} to {}", last.raw, raw)
+        };
+        let event = Event::new(
+            EventKind::DeviceReset,
+            format!("device counter {}, starting epoch {}", change, self.epoch),
+        );
+        (last.unwrapped + host_gap_ms, Some(event))
+    }
+}
+
+impl Stage for DeviceClock {
+    fn headers(&self) -> Vec<String> {
+        vec![EPOCH_HEADER.to_string(), TIME_HEADER.to_string()]
+    }
+
+    fn process(&mut self, received: &HostTime, values: &mut Vec<Value>) -> Vec<Event> {
+        let Some(Value::U32(raw)) = values.get(self.column).cloned() else {
+            return Vec::new();
+        };
+
+        let (unwrapped, event) = self.unwrap(raw, received);
+        self.last = Some(Last {
+            raw,
+            unwrapped,
+            received: *received,
+        });
+
+        values.push(Value::U32(self.epoch));
+        values.push(Value::U64(unwrapped));
+        event.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use super::*;
+
+    /// Feed the clock a counter value received `seconds` into the session,
+    /// returning the epoch, unwrapped time and any event.
+    fn tick(clock: &mut DeviceClock, seconds: u64, raw: u32) -> (u32, u64, Option<EventKind>) {
+        let received = HostTime {
+            wall: Utc::now(),
+            elapsed: Duration::from_secs(seconds),
+        };
+        let mut values = vec![Value::U32(raw)];
+        let events = clock.process(&received, &mut values);
+        let (Value::U32(epoch), Value::U64(unwrapped)) = (&values[1], &values[2]) else {
+            panic!("unexpected columns {:?}", values);
+        };
+        (*epoch, *unwrapped, events.first().map(|event| event.kind))
+    }
+
+    #[test]
+    fn follows_the_counter() {
+        let mut clock = DeviceClock::new(0);
+        assert_eq!(tick(&mut clock, 0, 5_000), (0, 5_000, None));
+        assert_eq!(tick(&mut clock, 10, 15_010), (0, 15_010, None));
+    }
+
+    #[test]
+    fn carries_a_wrap() {
+        let mut clock = DeviceClock::new(0);
+        tick(&mut clock, 0, u32::MAX - 4_999);
+        assert_eq!(
+            tick(&mut clock, 10, 5_000),
+            (0, u32::MAX as u64 + 5_001, Some(EventKind::CounterWrap))
+        );
+        assert_eq!(
+            tick(&mut clock, 20, 15_000),
+            (0, u32::MAX as u64 + 15_001, None)
+        );
+    }
+
+    #[test]
+    fn bridges_a_reset_with_host_time() {
+        let mut clock = DeviceClock::new(0);
+        tick(&mut clock, 0, 600_000);
+        assert_eq!(
+            tick(&mut clock, 10, 200),
+            (1, 610_000, Some(EventKind::DeviceReset))
+        );
+        assert_eq!(tick(&mut clock, 20, 10_200), (1, 620_000, None));
+    }
+
+    #[test]
+    fn notices_a_reset_during_a_long_disconnect() {
+        let mut clock = DeviceClock::new(0);
+        tick(&mut clock, 0, 120_000);
+        // An hour later the board has restarted and been up for five minutes,
+        // so its counter is ahead of where it was.
+        assert_eq!(
+            tick(&mut clock, 3_600, 300_000),
+            (1, 3_720_000, Some(EventKind::DeviceReset))
+        );
+        assert_eq!(tick(&mut clock, 3_610, 310_000), (1, 3_730_000, None));
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use chrono::SecondsFormat;
+
+use crate::clock::HostTime;
+use crate::decoder::Value;
+use crate::device_clock::{EPOCH_HEADER, TIME_HEADER};
+use crate::events::Event;
+use crate::report::median;
+use crate::stage::Stage;
+
+/// How many recent samples the fit is computed over.
+const WINDOW: usize = 240;
+
+/// How often (in samples) the current estimate is printed.
+const REPORT_EVERY: u64 = 30;
+
+/// Estimates how the device's clock relates to the host's.
+///
+/// Fits `host_ms = offset + rate * device_ms` over a sliding window with the
+/// Theil-Sen estimator (median of pairwise slopes), which shrugs off the odd
+/// sample delayed by USB or scheduling. The fit restarts whenever the device
+/// epoch changes, since a reset throws the offset away.
+pub struct DriftEstimator {
+    epoch_column: usize,
+    time_column: usize,
+    write_corrected: bool,
+    epoch: Option<u32>,
+    points: VecDeque<(f64, f64)>,
+    fit: Fit,
+    samples: u64,
+    report_due: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Fit {
+    offset: f64,
+    rate: f64,
+}
+
+impl Fit {
+    fn ppm(&self) -> f64 {
+        (self.rate - 1.0) * 1e6
+    }
+}
+
+impl DriftEstimator {
+    /// `epoch_column` and `time_column` are the indices of the device epoch and
+    /// unwrapped device time, as produced by [`crate::DeviceClock`].
+    pub fn new(epoch_column: usize, time_column: usize, write_corrected: bool) -> Self {
+        DriftEstimator {
+            epoch_column,
+            time_column,
+            write_corrected,
+            epoch: None,
+            points: VecDeque::with_capacity(WINDOW),
+            fit: Fit {
+                offset: 0.0,
+                rate: 1.0,
+            },
+            samples: 0,
+            report_due: false,
+        }
+    }
+
+    /// Follow the [`crate::DeviceClock`] columns among `headers`, those of the
+    /// row before this stage, or `None` if there isn't a device clock.
+    pub fn following(headers: &[String], write_corrected: bool) -> Option<Self> {
+        let column = |name| headers.iter().position(|header| header == name);
+        Some(DriftEstimator::new(
+            column(EPOCH_HEADER)?,
+            column(TIME_HEADER)?,
+            write_corrected,
+        ))
+    }
+
+    /// The current estimate as a human readable line.
+    fn report(&self) -> String {
+        format!(
+            "Device clock drift: {:+.1} ppm, offset {:.0} ms over {} samples",
+            self.fit.ppm(),
+            self.fit.offset,
+            self.points.len()
+        )
+    }
+
+    fn refit(&mut self) {
+        let points = self.points.make_contiguous();
+
+        let mut slopes = Vec::with_capacity(points.len() * points.len() / 2);
+        for (i, &(x1, y1)) in points.iter().enumerate() {
+            for &(x2, y2) in &points[i + 1..] {
+                if x2 != x1 {
+                    slopes.push((y2 - y1) / (x2 - x1));
+                }
+            }
+        }
+        let rate = median(&mut slopes).unwrap_or(1.0);
+
+        let mut offsets: Vec<f64> = points.iter().map(|&(x, y)| y - rate * x).collect();
+        let offset = median(&mut offsets).unwrap_or(0.0);
+
+        self.fit = Fit { offset, rate };
+    }
+}
+
+impl Stage for DriftEstimator {
+    fn headers(&self) -> Vec<String> {
+        if self.write_corrected {
+            vec!["Corrected Time".to_string()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn process(&mut self, received: &HostTime, values: &mut Vec<Value>) -> Vec<Event> {
+        let (Some(Value::U32(epoch)), Some(Value::U64(device_ms))) = (
+            values.get(self.epoch_column).cloned(),
+            values.get(self.time_column).cloned(),
+        ) else {
+            return Vec::new();
+        };
+
+        if self.epoch != Some(epoch) {
+            self.epoch = Some(epoch);
+            self.points.clear();
+        }
+        if self.points.len() == WINDOW {
+            self.points.pop_front();
+        }
+        let host_ms = received.elapsed.as_secs_f64() * 1000.0;
+        self.points.push_back((device_ms as f64, host_ms));
+        self.refit();
+
+        self.samples += 1;
+        self.report_due = self.samples.is_multiple_of(REPORT_EVERY);
+
+        if self.write_corrected {
+            // Map the device timestamp onto the host's timeline.
+            let corrected_ms = self.fit.offset + self.fit.rate * device_ms as f64;
+            let correction = Duration::from_secs_f64(corrected_ms.max(0.0) / 1000.0);
+            let session_start = received.wall - received.elapsed;
+            let corrected = session_start + correction;
+            values.push(Value::Text(
+                corrected.to_rfc3339_opts(SecondsFormat::Millis, true),
+            ));
+        }
+
+        Vec::new()
+    }
+
+    fn progress(&mut self) -> Option<String> {
+        std::mem::take(&mut self.report_due).then(|| self.report())
+    }
+
+    fn summary(&self) -> Option<String> {
+        (self.points.len() > 1).then(|| self.report())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use super::*;
+
+    /// Device milliseconds, and host milliseconds for a device clock that runs
+    /// 100 ppm slow and started 500 ms into the session.
+    fn pair(device_ms: u64) -> (u64, f64) {
+        (device_ms, 500.0 + device_ms as f64 * 1.0001)
+    }
+
+    /// When a line arrives `host_ms` into a session that started at noon.
+    fn received(host_ms: f64) -> HostTime {
+        let start: DateTime<Utc> = "2026-03-01T12:00:00Z".parse().unwrap();
+        let elapsed = Duration::from_secs_f64(host_ms / 1000.0);
+        HostTime {
+            wall: start + elapsed,
+            elapsed,
+        }
+    }
+
+    fn feed(drift: &mut DriftEstimator, epoch: u32, (device_ms, host_ms): (u64, f64)) {
+        let mut values = vec![Value::U32(epoch), Value::U64(device_ms)];
+        drift.process(&received(host_ms), &mut values);
+    }
+
+    #[test]
+    fn fits_the_drift() {
+        let mut drift = DriftEstimator::new(0, 1, false);
+        for i in 0..60 {
+            feed(&mut drift, 0, pair(i * 1000));
+        }
+        // A sample held up by USB doesn't move the fit.
+        let (device_ms, host_ms) = pair(60_000);
+        feed(&mut drift, 0, (device_ms, host_ms + 250.0));
+
+        assert!((drift.fit.rate - 1.0001).abs() < 1e-9, "{:?}", drift.fit);
+        assert!((drift.fit.ppm() - 100.0).abs() < 1e-3, "{:?}", drift.fit);
+        assert!((drift.fit.offset - 500.0).abs() < 1e-3, "{:?}", drift.fit);
+    }
+
+    #[test]
+    fn starts_again_after_a_reset() {
+        let mut drift = DriftEstimator::new(0, 1, false);
+        for i in 0..10 {
+            feed(&mut drift, 0, pair(i * 1000));
+        }
+        assert_eq!(drift.points.len(), 10);
+
+        // The device restarted 20 s into the session with its clock at zero.
+        feed(&mut drift, 1, (0, 20_000.0));
+        assert_eq!(drift.points.len(), 1);
+        feed(&mut drift, 1, (1000, 21_000.1));
+        assert!((drift.fit.ppm() - 100.0).abs() < 1e-3, "{:?}", drift.fit);
+        assert!(
+            (drift.fit.offset - 20_000.0).abs() < 1e-3,
+            "{:?}",
+            drift.fit
+        );
+    }
+
+    #[test]
+    fn writes_the_corrected_time_after_the_device_clock() {
+        let headers = vec![
+            "Time (ms)".to_string(),
+            EPOCH_HEADER.to_string(),
+            TIME_HEADER.to_string(),
+        ];
+        assert!(DriftEstimator::following(&headers[..2], true).is_none());
+
+        let mut drift = DriftEstimator::following(&headers, true).unwrap();
+        assert_eq!((drift.epoch_column, drift.time_column), (1, 2));
+        assert_eq!(drift.headers(), ["Corrected Time"]);
+
+        let mut last = Vec::new();
+        for i in 0..5 {
+            let (device_ms, host_ms) = pair(i * 1000);
+            last = vec![
+                Value::U32(device_ms as u32),
+                Value::U32(0),
+                Value::U64(device_ms),
+            ];
+            drift.process(&received(host_ms), &mut last);
+        }
+        assert_eq!(last[3], Value::Text("2026-03-01T12:00:04.500Z".to_string()));
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Everything other than a regular sample that can show up on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum EventKind {
+    /// temp-monitor: the DHT11 data pin couldn't be driven ("Pin Error!").
+    PinError,
+    /// temp-monitor: the DHT11 checksum didn't match ("Checksum Mismatch!").
+    ChecksumMismatch,
+    /// temp-monitor: the DHT11 didn't answer in time ("Timeout!").
+    Timeout,
+    /// A line that doesn't match the device's format.
+    Unparsed,
+    /// A line that isn't valid UTF-8.
+    InvalidUtf8,
+    /// A line longer than the maximum length, which was discarded.
+    LineOverflow,
+    /// The device's timestamp jumped backwards because the board restarted.
+    DeviceReset,
+    /// The device's u32 timestamp overflowed back to zero.
+    CounterWrap,
+    /// The port stopped working, e.g. because the device was unplugged.
+    Disconnected,
+    /// The port was reopened after a disconnect.
+    Reconnected,
+    /// No valid sample arrived for several expected intervals.
+    Stale,
+    /// Samples arrived again after the stream went stale.
+    Resumed,
+    /// A command was written to the device.
+    CommandSent,
+    /// The device answered a command.
+    CommandReply,
+    /// The device didn't answer a command in time.
+    CommandTimeout,
+    /// A command couldn't be written to the device.
+    CommandFailed,
+    /// A value crossed an alarm threshold.
+    AlarmRaised,
+    /// A value came back from an alarm threshold.
+    AlarmCleared,
+    /// A filter replaced a value that was too far from its neighbours.
+    Outlier,
+    /// Something happened to an output rather than the device, e.g. its
+    /// files were rotated.
+    Output,
+}
+
+impl EventKind {
+    /// Stable identifier used in output files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::PinError => "pin_error",
+            EventKind::ChecksumMismatch => "checksum_mismatch",
+            EventKind::Timeout => "timeout",
+            EventKind::Unparsed => "unparsed",
+            EventKind::InvalidUtf8 => "invalid_utf8",
+            EventKind::LineOverflow => "line_overflow",
+            EventKind::DeviceReset => "device_reset",
+            EventKind::CounterWrap => "counter_wrap",
+            EventKind::Disconnected => "disconnected",
+            EventKind::Reconnected => "reconnected",
+            EventKind::Stale => "stale",
+            EventKind::Resumed => "resumed",
+            EventKind::CommandSent => "command_sent",
+            EventKind::CommandReply => "command_reply",
+            EventKind::CommandTimeout => "command_timeout",
+            EventKind::CommandFailed => "command_failed",
+            EventKind::AlarmRaised => "alarm_raised",
+            EventKind::AlarmCleared => "alarm_cleared",
+            EventKind::Outlier => "outlier",
+            EventKind::Output => "output",
+        }
+    }
+
+    /// Whether the firmware itself reported a failed measurement.
+    pub fn is_device_error(&self) -> bool {
+        matches!(
+            self,
+            EventKind::PinError | EventKind::ChecksumMismatch | EventKind::Timeout
+        )
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Something noticed while processing samples, such as a device reset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub detail: String,
+}
+
+impl Event {
+    pub fn new(kind: EventKind, detail: impl Into<String>) -> Self {
+        Event {
+            kind,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Keeps a count of each kind of event, and of the samples between them.
+#[derive(Debug, Default)]
+pub struct EventCounts {
+    counts: BTreeMap<EventKind, u64>,
+    samples: u64,
+}
+
+impl EventCounts {
+    pub fn record(&mut self, kind: EventKind) {
+        *self.counts.entry(kind).or_default() += 1;
+    }
+
+    /// Count a successfully decoded sample, for the failure rate.
+    pub fn count_sample(&mut self) {
+        self.samples += 1;
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    pub fn count(&self, kind: EventKind) -> u64 {
+        self.counts.get(&kind).copied().unwrap_or(0)
+    }
+
+    pub fn counts(&self) -> &BTreeMap<EventKind, u64> {
+        &self.counts
+    }
+
+    /// One-line summary of the samples and events seen so far.
+    pub fn summary(&self) -> String {
+        let device_errors: u64 = self
+            .counts
+            .iter()
+            .filter(|(kind, _)| kind.is_device_error())
+            .map(|(_, count)| count)
+            .sum();
+        let measurements = self.samples + device_errors;
+
+        let mut summary = format!("{} samples", self.samples);
+        if measurements > 0 {
+            summary += &format!(
+                ", {:.1}% of measurements failed",
+                100.0 * device_errors as f64 / measurements as f64
+            );
+        }
+        for (kind, count) in &self.counts {
+            summary += &format!(", {}: {}", kind, count);
+        }
+        summary
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::clock::HostTime;
+use crate::decoder::Value;
+use crate::events::{Event, EventKind};
+use crate::report::median;
+use crate::sinks::field_name;
+use crate::stage::Stage;
+
+/// Scales the median absolute deviation to the standard deviation of normally
+/// distributed values.
+const MAD_SCALE: f64 = 1.4826;
+
+/// Filtered values are rounded to this many decimal places, which is well
+/// past the resolution of any of the sensors.
+const DECIMALS: i32 = 3;
+
+/// How a filter turns a column's recent values into a filtered value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    /// Median of the last N values.
+    Median(usize),
+    /// Mean of the last N values.
+    Mean(usize),
+    /// Exponential moving average, giving each new value a weight of alpha.
+    Exponential(f64),
+    /// Hampel outlier rejection: a value more than `sigmas` standard
+    /// deviations (estimated from the median absolute deviation) from the
+    /// median of the last `window` values is replaced by that median.
+    Hampel { window: usize, sigmas: f64 },
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterKind::Median(window) => write!(f, "median {}", window),
+            FilterKind::Mean(window) => write!(f, "mean {}", window),
+            FilterKind::Exponential(alpha) => write!(f, "ema {}", alpha),
+            FilterKind::Hampel { window, .. } => write!(f, "hampel {}", window),
+        }
+    }
+}
+
+/// One filter, parsed from e.g. `rpm:median=5` or `temperature_f:hampel=7,sigmas=3`.
+///
+/// The kinds are `median=N` and `mean=N` over the last N samples, `ema=ALPHA`
+/// with 0 < ALPHA <= 1, and `hampel=N` with the option `sigmas=` (3 by
+/// default). The filtered column is named after the column and the filter,
+/// as in "RPM [median 5]", unless `name=` is given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterRule {
+    /// Column header or field name, e.g. "RPM" or "rpm".
+    pub column: String,
+    pub kind: FilterKind,
+    pub name: Option<String>,
+}
+
+impl FromStr for FilterRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let (column, filter) = parts
+            .next()
+            .unwrap_or_default()
+            .rsplit_once(':')
+            .ok_or_else(|| {
+                format!(
+                    "Invalid filter {:?}, expected e.g. rpm:median=5 or temperature_f:ema=0.2",
+                    s
+                )
+            })?;
+        let (kind, parameter) = filter.split_once('=').unwrap_or((filter, ""));
+        let window = || {
+            parameter
+                .trim()
+                .parse()
+                .ok()
+                .filter(|n: &usize| *n >= 2)
+                .ok_or_else(|| format!("Invalid window {:?} in filter {:?}", parameter, s))
+        };
+        let mut kind = match kind.trim() {
+            "median" => FilterKind::Median(window()?),
+            "mean" => FilterKind::Mean(window()?),
+            "ema" => FilterKind::Exponential(
+                parameter
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|alpha: &f64| *alpha > 0.0 && *alpha <= 1.0)
+                    .ok_or_else(|| format!("Invalid alpha {:?} in filter {:?}", parameter, s))?,
+            ),
+            "hampel" => FilterKind::Hampel {
+                window: window()?,
+                sigmas: 3.0,
+            },
+            _ => {
+                return Err(format!(
+                    "Unknown filter {:?} in {:?}, expected median, mean, ema or hampel",
+                    kind, s
+                ))
+            }
+        };
+
+        let mut name = None;
+        for option in parts {
+            match (option.split_once('='), &mut kind) {
+                (Some(("sigmas", value)), FilterKind::Hampel { sigmas, .. }) => {
+                    *sigmas = value
+                        .trim()
+                        .parse()
+                        .ok()
+                        .filter(|k: &f64| *k > 0.0)
+                        .ok_or_else(|| format!("Invalid sigmas {:?} in filter {:?}", value, s))?
+                }
+                (Some(("name", value)), _) => name = Some(value.trim().to_string()),
+                _ => {
+                    return Err(format!(
+                        "Unknown option {:?} in filter {:?}, expected name= or sigmas=",
+                        option, s
+                    ))
+                }
+            }
+        }
+
+        Ok(FilterRule {
+            column: column.trim().to_string(),
+            kind,
+            name,
+        })
+    }
+}
+
+/// A rule tied to its column, with the values it's seen.
+struct Filter {
+    rule: FilterRule,
+    column: usize,
+    header: String,
+    /// Header of the filtered column.
+    output: String,
+    /// The last values of the column, for the windowed filters.
+    recent: VecDeque<f64>,
+    /// The running average, for `ema`.
+    average: Option<f64>,
+}
+
+impl Filter {
+    /// The filtered value for `value`, and the event if it was rejected as an
+    /// outlier.
+    fn apply(&mut self, value: f64) -> (f64, Option<Event>) {
+        let window = match self.rule.kind {
+            FilterKind::Median(window)
+            | FilterKind::Mean(window)
+            | FilterKind::Hampel { window, .. } => window,
+            FilterKind::Exponential(alpha) => {
+                let average = match self.average {
+                    Some(average) => average + alpha * (value - average),
+                    None => value,
+                };
+                self.average = Some(average);
+                return (average, None);
+            }
+        };
+        self.recent.push_back(value);
+        if self.recent.len() > window {
+            self.recent.pop_front();
+        }
+
+        match self.rule.kind {
+            FilterKind::Mean(_) => {
+                let mean = self.recent.iter().sum::<f64>() / self.recent.len() as f64;
+                (mean, None)
+            }
+            FilterKind::Hampel { sigmas, .. } => {
+                let mut values: Vec<f64> = self.recent.iter().copied().collect();
+                let median = median(&mut values).unwrap_or(value);
+                let mut deviations: Vec<f64> = values.iter().map(|v| (v - median).abs()).collect();
+                let limit = sigmas * MAD_SCALE * self::median(&mut deviations).unwrap_or(0.0);
+                // A window of one or two values can't tell which is wrong.
+                if values.len() < 3 || (value - median).abs() <= limit {
+                    return (value, None);
+                }
+                let event = Event::new(
+                    EventKind::Outlier,
+                    format!(
+                        "{} = {} replaced by {} ({})",
+                        self.header, value, median, self.rule.kind
+                    ),
+                );
+                (median, Some(event))
+            }
+            _ => {
+                let mut values: Vec<f64> = self.recent.iter().copied().collect();
+                (median(&mut values).unwrap_or(value), None)
+            }
+        }
+    }
+}
+
+/// Appends a smoothed or cleaned-up copy of columns to each row, leaving the
+/// raw values as they were.
+///
+/// Hampel filters record every value they replace as an `outlier` event.
+/// Filters can be applied to the columns of earlier filters, e.g. a median
+/// of the values a Hampel filter has let through.
+pub struct Filters {
+    filters: Vec<Filter>,
+}
+
+impl Filters {
+    /// `headers` are those of the row so far; each filter can also use the
+    /// columns of the filters before it.
+    pub fn new(rules: Vec<FilterRule>, headers: &[String]) -> Result<Self, String> {
+        let mut headers = headers.to_vec();
+        let mut filters = Vec::new();
+        for rule in rules {
+            let wanted = field_name(&rule.column);
+            let column = headers
+                .iter()
+                .position(|header| *header == rule.column || field_name(header) == wanted)
+                .ok_or_else(|| {
+                    format!(
+                        "Filter on unknown column {:?}; the columns are {}",
+                        rule.column,
+                        headers.join(", ")
+                    )
+                })?;
+            let header = headers[column].clone();
+            let output = rule
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("{} [{}]", header, rule.kind));
+            headers.push(output.clone());
+            filters.push(Filter {
+                rule,
+                column,
+                header,
+                output,
+                recent: VecDeque::new(),
+                average: None,
+            });
+        }
+        Ok(Filters { filters })
+    }
+}
+
+impl Stage for Filters {
+    fn headers(&self) -> Vec<String> {
+        self.filters
+            .iter()
+            .map(|filter| filter.output.clone())
+            .collect()
+    }
+
+    fn process(&mut self, _received: &HostTime, values: &mut Vec<Value>) -> Vec<Event> {
+        let mut events = Vec::new();
+        for filter in self.filters.iter_mut() {
+            // Keep the columns lined up even when there's nothing to filter.
+            let Some(value) = values.get(filter.column).and_then(Value::as_f64) else {
+                values.push(Value::Text(String::new()));
+                continue;
+            };
+            let (filtered, event) = filter.apply(value);
+            let scale = 10f64.powi(DECIMALS);
+            values.push(Value::F64((filtered * scale).round() / scale));
+            events.extend(event);
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(rule: &str) -> Filter {
+        let rule: FilterRule = rule.parse().unwrap();
+        let headers = ["RPM".to_string()];
+        Filters::new(vec![rule], &headers)
+            .unwrap()
+            .filters
+            .remove(0)
+    }
+
+    fn run(rule: &str, values: &[f64]) -> Vec<f64> {
+        let mut filter = filter(rule);
+        values.iter().map(|&v| filter.apply(v).0).collect()
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rule: FilterRule = "temperature_f:hampel=7,sigmas=2.5,name=Clean"
+            .parse()
+            .unwrap();
+        assert_eq!(rule.column, "temperature_f");
+        assert_eq!(
+            rule.kind,
+            FilterKind::Hampel {
+                window: 7,
+                sigmas: 2.5
+            }
+        );
+        assert_eq!(rule.name.as_deref(), Some("Clean"));
+
+        assert!("rpm".parse::<FilterRule>().is_err());
+        assert!("rpm:median=1".parse::<FilterRule>().is_err());
+        assert!("rpm:ema=1.5".parse::<FilterRule>().is_err());
+        assert!("rpm:mean=5,sigmas=3".parse::<FilterRule>().is_err());
+    }
+
+    #[test]
+    fn names_the_filtered_columns() {
+        let rules = vec![
+            "rpm:hampel=5".parse().unwrap(),
+            "rpm_hampel_5:median=3".parse().unwrap(),
+        ];
+        let filters = Filters::new(rules, &["RPM".to_string()]).unwrap();
+        assert_eq!(
+            filters.headers(),
+            ["RPM [hampel 5]", "RPM [hampel 5] [median 3]"]
+        );
+        assert_eq!(filters.filters[1].column, 1);
+
+        let unknown = vec!["temperature_f:mean=3".parse().unwrap()];
+        assert!(Filters::new(unknown, &["RPM".to_string()]).is_err());
+    }
+
+    #[test]
+    fn smooths_over_the_window() {
+        let values = [0.0, 10.0, 20.0, 30.0, 1000.0];
+        assert_eq!(run("rpm:mean=2", &values), [0.0, 5.0, 15.0, 25.0, 515.0]);
+        assert_eq!(run("rpm:median=3", &values), [0.0, 5.0, 10.0, 20.0, 30.0]);
+        assert_eq!(
+            run("rpm:ema=0.5", &values),
+            [0.0, 5.0, 12.5, 21.25, 510.625]
+        );
+    }
+
+    #[test]
+    fn hampel_replaces_false_zeros() {
+        let mut filter = filter("rpm:hampel=5");
+        let values = [1260.0, 1160.0, 1178.0, 0.0, 1260.0, 1200.0];
+        let filtered: Vec<(f64, bool)> = values
+            .iter()
+            .map(|&v| {
+                let (value, event) = filter.apply(v);
+                (value, event.is_some())
+            })
+            .collect();
+        assert_eq!(
+            filtered,
+            [
+                (1260.0, false),
+                (1160.0, false),
+                (1178.0, false),
+                (1169.0, true),
+                (1260.0, false),
+                (1200.0, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn hampel_follows_a_real_step_after_half_a_window() {
+        let values = [20.0, 20.0, 20.0, 25.0, 25.0, 25.0];
+        assert_eq!(
+            run("rpm:hampel=5", &values),
+            [20.0, 20.0, 20.0, 20.0, 20.0, 25.0]
+        );
+    }
+}
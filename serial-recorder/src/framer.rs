@@ -0,0 +1,243 @@
+/// One unit of output from a [`LineFramer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// A complete line, without its terminator.
+    Line(Vec<u8>),
+    /// A line grew past the maximum length, so all `discarded` bytes of it up
+    /// to the next terminator were thrown away.
+    Overflow { discarded: usize },
+}
+
+/// Splits a byte stream into lines, however it happens to be chunked.
+///
+/// Lines may end in LF, CR or CRLF, including a CRLF split across two
+/// chunks. Every complete line in a chunk is returned straight away, and a
+/// device that never sends a terminator can't make the buffer grow past
+/// `max_length`.
+#[derive(Debug)]
+pub struct LineFramer {
+    buffer: Vec<u8>,
+    max_length: usize,
+    /// Bytes dropped from the current line once it overflowed.
+    discarded: Option<usize>,
+    /// The previous byte was a CR, so an LF right after it isn't a new line.
+    after_cr: bool,
+}
+
+impl LineFramer {
+    pub fn new(max_length: usize) -> Self {
+        LineFramer {
+            buffer: Vec::new(),
+            max_length,
+            discarded: None,
+            after_cr: false,
+        }
+    }
+
+    /// Add bytes from the device and return every frame they complete.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Frame> {
+        let mut frames = Vec::new();
+
+        for &byte in bytes {
+            let after_cr = std::mem::replace(&mut self.after_cr, byte == b'\r');
+            match byte {
+                b'\n' if after_cr => (),
+                b'\n' | b'\r' => frames.push(self.end_line()),
+                _ => match self.discarded.as_mut() {
+                    Some(discarded) => *discarded += 1,
+                    None if self.buffer.len() == self.max_length => {
+                        self.discarded = Some(self.buffer.len() + 1);
+                        self.buffer.clear();
+                    }
+                    None => self.buffer.push(byte),
+                },
+            }
+        }
+
+        frames
+    }
+
+    /// The unterminated remainder once the input has ended, if any.
+    pub fn finish(&mut self) -> Option<Frame> {
+        self.after_cr = false;
+        if self.buffer.is_empty() && self.discarded.is_none() {
+            None
+        } else {
+            Some(self.end_line())
+        }
+    }
+
+    /// Forget any partial line, e.g. after the device was reconnected.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.discarded = None;
+        self.after_cr = false;
+    }
+
+    fn end_line(&mut self) -> Frame {
+        match self.discarded.take() {
+            Some(discarded) => Frame::Overflow { discarded },
+            None => Frame::Line(std::mem::take(&mut self.buffer)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn lines(frames: Vec<Frame>) -> Vec<String> {
+        frames
+            .into_iter()
+            .map(|frame| match frame {
+                Frame::Line(line) => String::from_utf8(line).unwrap(),
+                Frame::Overflow { discarded } => format!("<overflow {}>", discarded),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn returns_every_line_in_a_chunk() {
+        let mut framer = LineFramer::new(64);
+        assert_eq!(
+            lines(framer.push(b"1,72.5\n2,73.1\n3,")),
+            ["1,72.5", "2,73.1"]
+        );
+        assert_eq!(lines(framer.push(b"74.0\n")), ["3,74.0"]);
+    }
+
+    #[test]
+    fn handles_each_line_ending() {
+        let mut framer = LineFramer::new(64);
+        assert_eq!(
+            lines(framer.push(b"lf\ncr\rcrlf\r\nend\n")),
+            ["lf", "cr", "crlf", "end"]
+        );
+    }
+
+    #[test]
+    fn crlf_split_across_chunks_is_one_line_ending() {
+        let mut framer = LineFramer::new(64);
+        assert_eq!(lines(framer.push(b"840\r")), ["840"]);
+        assert_eq!(lines(framer.push(b"\n1260\r")), ["1260"]);
+        assert_eq!(lines(framer.push(b"\n")), Vec::<String>::new());
+    }
+
+    #[test]
+    fn keeps_blank_lines_that_are_really_there() {
+        let mut framer = LineFramer::new(64);
+        assert_eq!(lines(framer.push(b"a\n\nb\r\n\r\n")), ["a", "", "b", ""]);
+    }
+
+    #[test]
+    fn overflow_discards_the_rest_of_the_line() {
+        let mut framer = LineFramer::new(4);
+        assert_eq!(lines(framer.push(b"1234")), Vec::<String>::new());
+        assert_eq!(lines(framer.push(b"5678")), Vec::<String>::new());
+        assert_eq!(lines(framer.push(b"9\nok\n")), ["<overflow 9>", "ok"]);
+        assert!(framer.buffer.capacity() <= 8);
+    }
+
+    #[test]
+    fn line_of_exactly_max_length_is_kept() {
+        let mut framer = LineFramer::new(4);
+        assert_eq!(
+            lines(framer.push(b"1234\n12345\n")),
+            ["1234", "<overflow 5>"]
+        );
+    }
+
+    #[test]
+    fn finish_returns_the_unterminated_tail() {
+        let mut framer = LineFramer::new(64);
+        assert_eq!(lines(framer.push(b"1\n2")), ["1"]);
+        assert_eq!(framer.finish(), Some(Frame::Line(b"2".to_vec())));
+        assert_eq!(framer.finish(), None);
+    }
+
+    #[test]
+    fn clear_drops_a_partial_line() {
+        let mut framer = LineFramer::new(64);
+        framer.push(b"12");
+        framer.clear();
+        assert_eq!(lines(framer.push(b"34\n")), ["34"]);
+    }
+
+    /// Any way of chunking a stream gives the same lines.
+    #[test]
+    fn chunking_does_not_change_the_result() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        let endings: [&[u8]; 3] = [b"\n", b"\r", b"\r\n"];
+
+        for _ in 0..500 {
+            let mut stream = Vec::new();
+            let mut expected = Vec::new();
+            for _ in 0..rng.gen_range(0..20) {
+                let line: String = (0..rng.gen_range(1..12))
+                    .map(|_| rng.gen_range(b'!'..=b'~') as char)
+                    .collect();
+                stream.extend_from_slice(line.as_bytes());
+                stream.extend_from_slice(endings[rng.gen_range(0..endings.len())]);
+                expected.push(line);
+            }
+
+            let mut framer = LineFramer::new(64);
+            let mut framed = Vec::new();
+            let mut rest = stream.as_slice();
+            while !rest.is_empty() {
+                let (chunk, tail) = rest.split_at(rng.gen_range(1..=rest.len()));
+                framed.extend(lines(framer.push(chunk)));
+                rest = tail;
+            }
+
+            assert_eq!(framed, expected);
+            assert_eq!(framer.finish(), None);
+        }
+    }
+
+    /// Arbitrary bytes never panic, never produce an over-long line and never
+    /// lose track of the bytes fed in.
+    #[test]
+    fn random_bytes_are_bounded() {
+        let mut rng = StdRng::seed_from_u64(0xbad_5eed);
+
+        for _ in 0..500 {
+            let max_length = rng.gen_range(1..32);
+            let mut framer = LineFramer::new(max_length);
+            let stream: Vec<u8> = (0..rng.gen_range(0..512))
+                .map(|_| match rng.gen_range(0..10) {
+                    0 => b'\n',
+                    1 => b'\r',
+                    _ => rng.gen(),
+                })
+                .collect();
+
+            let mut frames = Vec::new();
+            for chunk in stream.chunks(rng.gen_range(1..64)) {
+                frames.extend(framer.push(chunk));
+                assert!(framer.buffer.len() <= max_length);
+            }
+            frames.extend(framer.finish());
+
+            let mut accounted = 0;
+            for frame in frames {
+                match frame {
+                    Frame::Line(line) => {
+                        assert!(line.len() <= max_length);
+                        assert!(!line.contains(&b'\n') && !line.contains(&b'\r'));
+                        accounted += line.len();
+                    }
+                    Frame::Overflow { discarded } => {
+                        assert!(discarded > max_length);
+                        accounted += discarded;
+                    }
+                }
+            }
+            let content = stream.iter().filter(|&&b| b != b'\n' && b != b'\r').count();
+            assert_eq!(accounted, content);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Shared plumbing for the serial recorders.
+//!
+//! Every recorder in this repository does the same thing: open a serial port,
+//! split whatever the device sends into lines, parse each line and append the
+//! result to one or more output files. Only the parsing differs between devices, so that part
+//! lives behind the [`LineDecoder`] trait and everything else is shared.
+
+pub mod alarm;
+pub mod analyze;
+pub mod cli;
+pub mod clock;
+pub mod commands;
+pub mod dashboard;
+pub mod decoder;
+pub mod decoders;
+pub mod device_clock;
+pub mod drift;
+pub mod events;
+pub mod filter;
+pub mod framer;
+pub mod metrics;
+pub mod multi;
+pub mod plot;
+pub mod port;
+pub mod recorded;
+pub mod recorder;
+pub mod replay;
+pub mod report;
+pub mod sinks;
+pub mod source;
+pub mod stage;
+pub mod units;
+pub mod watchdog;
+
+pub use alarm::{AlarmRule, Alarms};
+pub use clock::{HostTime, SessionClock};
+pub use commands::{Command, CommandChannel};
+pub use dashboard::Dashboard;
+pub use decoder::{DecodeError, LineDecoder, Value};
+pub use device_clock::DeviceClock;
+pub use drift::DriftEstimator;
+pub use events::{Event, EventCounts, EventKind};
+pub use filter::{FilterRule, Filters};
+pub use framer::{Frame, LineFramer};
+pub use metrics::MetricsServer;
+pub use multi::DeviceSpec;
+pub use port::SerialSource;
+pub use recorder::{Recorder, RecorderOptions};
+pub use replay::Replay;
+pub use report::SessionReport;
+pub use sinks::Sink;
+pub use source::Source;
+pub use stage::Stage;
+pub use watchdog::Watchdog;
@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Arg, ArgGroup};
+use serial_recorder::{
+    cli, decoders::parse_delimiter, decoders::Schema, decoders::SchemaDecoder, RecorderOptions,
+};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let matches = cli::command("Serial Recorder")
+        .about("Records any device that prints delimited values, described by a schema.")
+        .arg(
+            Arg::new("schema")
+                .long("schema")
+                .value_name("FIELDS")
+                .help("Fields of each line as name:type, e.g. \"ts:u32,temp_f:f32,humidity:u8\"."),
+        )
+        .arg(
+            Arg::new("schema-file")
+                .long("schema-file")
+                .value_name("FILE")
+                .help("Read the fields, one name: type per line, and delimiter from a file.")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .group(
+            ArgGroup::new("fields")
+                .args(["schema", "schema-file"])
+                .required(true),
+        )
+        .arg(
+            Arg::new("delimiter")
+                .long("delimiter")
+                .value_name("CHAR")
+                .help("What separates the fields on a line: a character, tab or space.")
+                .conflicts_with("schema-file")
+                .default_value(",")
+                .value_parser(parse_delimiter),
+        )
+        .arg(
+            Arg::new("device")
+                .long("device")
+                .value_name("NAME")
+                .help("Name for the device, used for the output files.")
+                .default_value("device"),
+        )
+        .get_matches();
+
+    if let Some(result) = cli::run_subcommand(&matches) {
+        return result;
+    }
+
+    let schema = match matches.get_one::<PathBuf>("schema-file") {
+        Some(path) => Schema::from_file(path)?,
+        None => Schema::parse(
+            matches
+                .get_one::<String>("schema")
+                .expect("Schema is required."),
+            *matches
+                .get_one::<char>("delimiter")
+                .expect("Delimiter has a default."),
+        )?,
+    };
+    let device = matches
+        .get_one::<String>("device")
+        .expect("Device has a default.");
+
+    cli::run(
+        &matches,
+        Box::new(SchemaDecoder::new(schema)),
+        Vec::new(),
+        RecorderOptions {
+            device: device.clone(),
+            output: format!("{}_data.csv", device).into(),
+            events: format!("{}_events.csv", device).into(),
+            timeout: Duration::from_secs(1),
+            // Without a known rate, pace replays and watch for silence at one
+            // line a second unless told otherwise.
+            interval: matches
+                .get_one::<Duration>("expect-every")
+                .copied()
+                .unwrap_or(Duration::from_secs(1)),
+        },
+    )
+}
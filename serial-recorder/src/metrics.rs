@@ -0,0 +1,351 @@
+//! A Prometheus `/metrics` endpoint with the live state of a recording.
+//!
+//! [`MetricsServer`] answers scrapes from a background thread. Each recorder
+//! feeds it through a [`MetricsSink`], so one endpoint can serve several
+//! devices at once.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
+
+use tiny_http::{Header, Response, Server};
+
+use crate::clock::HostTime;
+use crate::decoder::Value;
+use crate::events::{Event, EventKind};
+use crate::sinks::{self, SessionInfo, Sink};
+
+/// Content type of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// What's known about one device, as of the latest row.
+#[derive(Debug, Default)]
+struct DeviceState {
+    device: String,
+    /// Field names of the columns, used as the `channel` label.
+    channels: Vec<String>,
+    latest: Vec<Option<f64>>,
+    samples: u64,
+    errors: u64,
+    events: BTreeMap<EventKind, u64>,
+    last_sample: Option<Instant>,
+    connected: bool,
+}
+
+/// Serves the metrics of every [`MetricsSink`] made from it over HTTP.
+#[derive(Debug, Clone)]
+pub struct MetricsServer {
+    devices: Arc<Mutex<Vec<DeviceState>>>,
+}
+
+impl MetricsServer {
+    /// Listen on `address`, such as `0.0.0.0:9898`, or on a bare port number
+    /// on localhost only.
+    pub fn serve(address: &str) -> Result<Self, Box<dyn Error>> {
+        let address = match address.parse::<u16>() {
+            Ok(port) => format!("127.0.0.1:{}", port),
+            Err(_) => address.to_string(),
+        };
+        let server = Server::http(&address)
+            .map_err(|e| format!("Failed to serve metrics on {}: {}", address, e))?;
+
+        let devices = Arc::new(Mutex::new(Vec::new()));
+        let metrics = MetricsServer {
+            devices: Arc::clone(&devices),
+        };
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let path = request.url().split('?').next().unwrap_or_default();
+                let response = if path == "/metrics" {
+                    let body = render(&lock(&devices));
+                    let header = Header::from_bytes("Content-Type", CONTENT_TYPE)
+                        .expect("The content type is a valid header.");
+                    Response::from_string(body).with_header(header)
+                } else {
+                    Response::from_string("Not found, try /metrics\n").with_status_code(404)
+                };
+                // A scraper that hangs up early isn't the recording's problem.
+                let _ = request.respond(response);
+            }
+        });
+        Ok(metrics)
+    }
+
+    /// A sink that publishes the rows it's given as one device's metrics.
+    pub fn sink(&self) -> MetricsSink {
+        let mut devices = lock(&self.devices);
+        devices.push(DeviceState::default());
+        MetricsSink {
+            devices: Arc::clone(&self.devices),
+            index: devices.len() - 1,
+        }
+    }
+}
+
+/// Keeps one device's metrics up to date for a [`MetricsServer`].
+pub struct MetricsSink {
+    devices: Arc<Mutex<Vec<DeviceState>>>,
+    index: usize,
+}
+
+impl MetricsSink {
+    fn update(&self, change: impl FnOnce(&mut DeviceState)) {
+        change(&mut lock(&self.devices)[self.index]);
+    }
+}
+
+impl Sink for MetricsSink {
+    fn open(&mut self, session: &SessionInfo) -> Result<(), Box<dyn Error>> {
+        self.update(|state| {
+            state.device = session.device.clone();
+            state.channels = session
+                .headers
+                .iter()
+                .map(|header| sinks::field_name(header))
+                .collect();
+            state.latest = vec![None; session.headers.len()];
+            state.connected = true;
+        });
+        Ok(())
+    }
+
+    fn write_sample(
+        &mut self,
+        _received: &HostTime,
+        values: &[Value],
+    ) -> Result<(), Box<dyn Error>> {
+        self.update(|state| {
+            state.samples += 1;
+            state.last_sample = Some(Instant::now());
+            for (latest, value) in state.latest.iter_mut().zip(values) {
+                if let Some(value) = value.as_f64() {
+                    *latest = Some(value);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn write_event(&mut self, _received: &HostTime, event: &Event) -> Result<(), Box<dyn Error>> {
+        self.update(|state| {
+            *state.events.entry(event.kind).or_default() += 1;
+            match event.kind {
+                EventKind::Disconnected => state.connected = false,
+                EventKind::Reconnected => state.connected = true,
+                kind if is_error(kind) => state.errors += 1,
+                _ => {}
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Whether an event stands for a line that didn't become a sample.
+fn is_error(kind: EventKind) -> bool {
+    kind.is_device_error()
+        || matches!(
+            kind,
+            EventKind::Unparsed | EventKind::InvalidUtf8 | EventKind::LineOverflow
+        )
+}
+
+fn lock(devices: &Mutex<Vec<DeviceState>>) -> MutexGuard<'_, Vec<DeviceState>> {
+    // A panic elsewhere doesn't make the counters any less useful.
+    devices
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Format every device's metrics in the Prometheus text format.
+fn render(devices: &[DeviceState]) -> String {
+    let mut out = String::new();
+
+    family(
+        &mut out,
+        "value",
+        "gauge",
+        "Latest value of each numeric column.",
+    );
+    for state in devices {
+        for (channel, value) in state.channels.iter().zip(&state.latest) {
+            if let Some(value) = value {
+                let labels = [("device", state.device.as_str()), ("channel", channel)];
+                sample(&mut out, "value", &labels, *value);
+            }
+        }
+    }
+
+    family(
+        &mut out,
+        "samples_total",
+        "counter",
+        "Lines decoded into samples.",
+    );
+    for state in devices {
+        let labels = [("device", state.device.as_str())];
+        sample(&mut out, "samples_total", &labels, state.samples as f64);
+    }
+
+    family(
+        &mut out,
+        "errors_total",
+        "counter",
+        "Lines that couldn't be decoded, including errors reported by the device.",
+    );
+    for state in devices {
+        let labels = [("device", state.device.as_str())];
+        sample(&mut out, "errors_total", &labels, state.errors as f64);
+    }
+
+    family(
+        &mut out,
+        "events_total",
+        "counter",
+        "Recorded events by kind.",
+    );
+    for state in devices {
+        for (kind, count) in &state.events {
+            let labels = [("device", state.device.as_str()), ("kind", kind.name())];
+            sample(&mut out, "events_total", &labels, *count as f64);
+        }
+    }
+
+    family(
+        &mut out,
+        "last_sample_age_seconds",
+        "gauge",
+        "Seconds since the last valid sample.",
+    );
+    for state in devices {
+        if let Some(last) = state.last_sample {
+            let labels = [("device", state.device.as_str())];
+            let age = last.elapsed().as_secs_f64();
+            sample(&mut out, "last_sample_age_seconds", &labels, age);
+        }
+    }
+
+    family(
+        &mut out,
+        "connected",
+        "gauge",
+        "1 while the device's port is open, 0 while reconnecting.",
+    );
+    for state in devices {
+        let labels = [("device", state.device.as_str())];
+        sample(&mut out, "connected", &labels, state.connected as u8 as f64);
+    }
+
+    out
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP serial_recorder_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE serial_recorder_{} {}", name, kind);
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: f64) {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect();
+    let value = match value {
+        v if v == f64::INFINITY => "+Inf".to_string(),
+        v if v == f64::NEG_INFINITY => "-Inf".to_string(),
+        v => v.to_string(),
+    };
+    let _ = writeln!(
+        out,
+        "serial_recorder_{}{{{}}} {}",
+        name,
+        labels.join(","),
+        value
+    );
+}
+
+/// Escape a label value as the text format requires.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use super::*;
+
+    #[test]
+    fn renders_samples_and_event_counts() {
+        let server = MetricsServer {
+            devices: Arc::new(Mutex::new(Vec::new())),
+        };
+        let mut sink = server.sink();
+        let received = HostTime {
+            wall: Utc::now(),
+            elapsed: Duration::from_secs(1),
+        };
+        sink.open(&SessionInfo {
+            device: "bench \"A\"\\2\nleft".to_string(),
+            source: "/dev/ttyUSB0".to_string(),
+            started_at: received.wall,
+            headers: vec!["Time (ms)".to_string(), "RPM".to_string()],
+        })
+        .unwrap();
+        sink.write_sample(&received, &[Value::U32(1000), Value::F32(1200.5)])
+            .unwrap();
+        for kind in [
+            EventKind::Unparsed,
+            EventKind::Unparsed,
+            EventKind::CommandSent,
+        ] {
+            sink.write_event(&received, &Event::new(kind, "")).unwrap();
+        }
+        sink.write_event(&received, &Event::new(EventKind::Disconnected, ""))
+            .unwrap();
+
+        let text = render(&lock(&server.devices));
+        let device = r#"device="bench \"A\"\\2\nleft""#;
+        for line in [
+            "# TYPE serial_recorder_value gauge".to_string(),
+            format!(
+                r#"serial_recorder_value{{{},channel="time_ms"}} 1000"#,
+                device
+            ),
+            format!(
+                r#"serial_recorder_value{{{},channel="rpm"}} 1200.5"#,
+                device
+            ),
+            "# TYPE serial_recorder_samples_total counter".to_string(),
+            format!("serial_recorder_samples_total{{{}}} 1", device),
+            format!("serial_recorder_errors_total{{{}}} 2", device),
+            format!(
+                r#"serial_recorder_events_total{{{},kind="unparsed"}} 2"#,
+                device
+            ),
+            format!(
+                r#"serial_recorder_events_total{{{},kind="command_sent"}} 1"#,
+                device
+            ),
+            format!(
+                r#"serial_recorder_events_total{{{},kind="disconnected"}} 1"#,
+                device
+            ),
+            format!("serial_recorder_connected{{{}}} 0", device),
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "{:?} not in\n{}",
+                line,
+                text
+            );
+        }
+        assert!(text.contains("serial_recorder_last_sample_age_seconds{"));
+    }
+}
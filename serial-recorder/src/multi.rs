@@ -0,0 +1,660 @@
+//! Recording several devices at once into one merged timeline.
+//!
+//! Each device gets a thread and a [`Recorder`] of its own, writing its own
+//! files as usual. Every row is also forwarded to a merger, which writes the
+//! rows of all of the devices to one CSV file in the order they were received,
+//! with the device name in each row.
+
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+use crate::cli;
+use crate::clock::{HostTime, SessionClock};
+use crate::decoder::{LineDecoder, Value};
+use crate::decoders::{RpmDecoder, Schema, SchemaDecoder, TemperatureDecoder};
+use crate::device_clock::DeviceClock;
+use crate::drift::DriftEstimator;
+use crate::events::Event;
+use crate::metrics::MetricsServer;
+use crate::port::SerialSource;
+use crate::recorder::{Recorder, RecorderOptions};
+use crate::replay::{parse_speed, Replay};
+use crate::report::SessionReport;
+use crate::sinks::{self, CsvSink, Rotation, SessionInfo, Sink, WriteMode};
+use crate::source::Source;
+use crate::stage::Stage;
+use crate::watchdog::Watchdog;
+
+/// How long a row is held back before it's merged, so that a row from a
+/// slower thread can still be put in front of it.
+const MERGE_DELAY: Duration = Duration::from_secs(1);
+
+/// How often the merger writes out rows while waiting for more.
+const MERGE_POLL: Duration = Duration::from_millis(200);
+
+/// How the lines of a device are decoded.
+#[derive(Debug, Clone)]
+pub enum DecoderKind {
+    /// tacho.ino's bare RPM readings.
+    Rpm,
+    /// temp-monitor's timestamped readings, with the clock stages that
+    /// temp-recorder adds.
+    Temperature,
+    /// Delimited values described by a schema file.
+    Schema(Schema),
+}
+
+impl DecoderKind {
+    /// The decoder and stages the device's own recorder would use.
+    pub fn build(&self) -> (Box<dyn LineDecoder>, Vec<Box<dyn Stage>>) {
+        match self {
+            DecoderKind::Rpm => (Box::new(RpmDecoder), Vec::new()),
+            DecoderKind::Temperature => {
+                let decoder = TemperatureDecoder;
+                let clock = DeviceClock::new(0);
+                let headers: Vec<String> = decoder
+                    .headers()
+                    .into_iter()
+                    .chain(clock.headers())
+                    .collect();
+                let drift = DriftEstimator::following(&headers, false)
+                    .expect("The device clock adds its columns.");
+                (Box::new(decoder), vec![Box::new(clock), Box::new(drift)])
+            }
+            DecoderKind::Schema(schema) => {
+                (Box::new(SchemaDecoder::new(schema.clone())), Vec::new())
+            }
+        }
+    }
+
+    /// How often the device sends a line.
+    pub fn interval(&self) -> Duration {
+        match self {
+            DecoderKind::Temperature => Duration::from_secs(10),
+            DecoderKind::Rpm | DecoderKind::Schema(_) => Duration::from_secs(1),
+        }
+    }
+
+    /// Read timeout for the device's serial port.
+    pub fn timeout(&self) -> Duration {
+        match self {
+            DecoderKind::Temperature => Duration::from_secs(5),
+            DecoderKind::Rpm | DecoderKind::Schema(_) => Duration::from_secs(1),
+        }
+    }
+}
+
+impl FromStr for DecoderKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rpm" => Ok(DecoderKind::Rpm),
+            "temperature" => Ok(DecoderKind::Temperature),
+            _ => match s.strip_prefix("schema:") {
+                Some(path) => Schema::from_file(Path::new(path))
+                    .map(DecoderKind::Schema)
+                    .map_err(|e| e.to_string()),
+                None => Err(format!(
+                    "Unknown decoder {:?}, expected rpm, temperature or schema:FILE",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// Where a device's bytes come from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    Port { name: String, baud_rate: u32 },
+    Replay(PathBuf),
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::Port { name, .. } => f.write_str(name),
+            Input::Replay(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// One device to record, given on the command line as
+/// `NAME=DECODER@PORT[:BAUD]` or `NAME=DECODER@replay:FILE`.
+#[derive(Debug, Clone)]
+pub struct DeviceSpec {
+    pub name: String,
+    pub decoder: DecoderKind,
+    pub input: Input,
+}
+
+impl FromStr for DeviceSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid device {:?}, expected NAME=DECODER@PORT[:BAUD] or NAME=DECODER@replay:FILE",
+                s
+            )
+        };
+        let (name, rest) = s.split_once('=').ok_or_else(invalid)?;
+        let (decoder, input) = rest.split_once('@').ok_or_else(invalid)?;
+        let name = name.trim();
+        if name.is_empty() || input.is_empty() {
+            return Err(invalid());
+        }
+
+        let input = match input.strip_prefix("replay:") {
+            Some(path) => Input::Replay(path.into()),
+            None => match input.rsplit_once(':') {
+                Some((name, baud)) if baud.parse::<u32>().is_ok() => Input::Port {
+                    name: name.to_string(),
+                    baud_rate: baud.parse().expect("Baud rate was just parsed."),
+                },
+                _ => Input::Port {
+                    name: input.to_string(),
+                    baud_rate: cli::DEFAULT_BAUD,
+                },
+            },
+        };
+
+        Ok(DeviceSpec {
+            name: name.to_string(),
+            decoder: decoder.trim().parse()?,
+            input,
+        })
+    }
+}
+
+/// The command line of the multi-device recorder.
+pub fn command(name: &'static str) -> Command {
+    Command::new(name)
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommands(cli::subcommands())
+        .arg(
+            Arg::new("device")
+                .long("device")
+                .value_name("NAME=DECODER@PORT[:BAUD]")
+                .help(
+                    "A device to record. DECODER is rpm, temperature or schema:FILE, and \
+                     PORT may be replay:FILE to read a raw capture. May be repeated.",
+                )
+                .required(true)
+                .action(ArgAction::Append)
+                .value_parser(clap::value_parser!(DeviceSpec)),
+        )
+        .arg(
+            Arg::new("merged")
+                .long("merged")
+                .value_name("TEMPLATE")
+                .help(
+                    "File for the rows of all of the devices in time order. Takes the \
+                     {date} and {time} placeholders.",
+                )
+                .default_value("merged_data.csv"),
+        )
+        .arg(
+            Arg::new("sink")
+                .long("sink")
+                .value_name("KIND[=PATH]")
+                .help(format!(
+                    "Output format to write for each device, may be repeated. One of {}.",
+                    sinks::SINK_KINDS.join(", ")
+                ))
+                .action(ArgAction::Append)
+                .default_value("csv"),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("TEMPLATE")
+                .help(
+                    "Output file for each device; must contain {device}. {date} and {time} \
+                     are replaced with the start date and time.",
+                ),
+        )
+        .arg(
+            Arg::new("append")
+                .long("append")
+                .help("Continue existing output files if their columns match.")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .help("Overwrite existing output files.")
+                .conflicts_with("append")
+                .action(ArgAction::SetTrue),
+        )
+        .args(cli::rotation_args())
+        .arg(
+            Arg::new("metrics")
+                .long("metrics")
+                .value_name("ADDRESS")
+                .help(
+                    "Serve the latest values and counters for Prometheus at \
+                     http://ADDRESS/metrics. A bare port listens on localhost only.",
+                ),
+        )
+        .args(cli::mqtt_args())
+        .arg(
+            Arg::new("speed")
+                .long("speed")
+                .help("Replay speed multiplier; 0 replays as fast as possible.")
+                .default_value("1")
+                .value_parser(parse_speed),
+        )
+        .arg(
+            Arg::new("stale-after")
+                .long("stale-after")
+                .value_name("N")
+                .help("Record a stale event after N expected intervals without a valid sample.")
+                .default_value("3")
+                .value_parser(clap::value_parser!(u32).range(1..)),
+        )
+}
+
+/// Record every device named on the command line until they've all ended or
+/// the recording is stopped.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    if let Some(result) = cli::run_subcommand(matches) {
+        return result;
+    }
+
+    let devices: Vec<DeviceSpec> = matches
+        .get_many::<DeviceSpec>("device")
+        .expect("Device is required.")
+        .cloned()
+        .collect();
+    for (i, device) in devices.iter().enumerate() {
+        if devices[..i].iter().any(|other| other.name == device.name) {
+            return Err(format!("Device {:?} is given twice", device.name).into());
+        }
+    }
+    if devices.len() > 1
+        && matches
+            .get_one::<String>("output")
+            .is_some_and(|template| !template.contains("{device}"))
+    {
+        return Err("--output must contain {device} when recording several devices".into());
+    }
+
+    // A port that won't open shouldn't leave an empty merged file behind to
+    // refuse the next attempt.
+    let inputs = devices
+        .iter()
+        .map(|device| open_input(matches, device).map_err(|e| format!("{}: {}", device.name, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let clock = SessionClock::start();
+    // Nor should a device's own file being in the way.
+    let device_sinks = devices
+        .iter()
+        .map(|device| {
+            own_sinks(matches, device, &clock).map_err(|e| format!("{}: {}", device.name, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let stop = cli::stop_on_signals()?;
+
+    let merged_path = PathBuf::from(cli::expand_template(
+        matches
+            .get_one::<String>("merged")
+            .expect("Merged has a default."),
+        "",
+        "merged",
+        clock.started_at(),
+    ));
+    let rotation = cli::rotation_from_matches(matches);
+    let mut merger = Merger::open(
+        &devices,
+        &merged_path,
+        write_mode(matches),
+        rotation.as_ref(),
+        &clock,
+    )?;
+
+    let metrics = match matches.get_one::<String>("metrics") {
+        Some(address) => Some(MetricsServer::serve(address)?),
+        None => None,
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    let reports = thread::scope(|scope| {
+        let threads: Vec<_> = devices
+            .iter()
+            .zip(inputs)
+            .zip(device_sinks)
+            .enumerate()
+            .map(|(index, ((device, input), mut sinks))| {
+                sinks.push(Box::new(Forward {
+                    device: index,
+                    sender: sender.clone(),
+                }));
+                if let Some(metrics) = &metrics {
+                    sinks.push(Box::new(metrics.sink()));
+                }
+                let stop = Arc::clone(&stop);
+                scope.spawn(move || {
+                    let result = record_device(matches, device, input, sinks, clock, &stop)
+                        .map_err(|e| format!("{}: {}", device.name, e));
+                    // Without one of the devices the merged timeline has a
+                    // hole in it, so stop the others too.
+                    if result.is_err() {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    result
+                })
+            })
+            .collect();
+        drop(sender);
+
+        let mut merged = Ok(());
+        loop {
+            match receiver.recv_timeout(MERGE_POLL) {
+                Ok(entry) => merger.push(entry),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            let before = clock.now().elapsed.saturating_sub(MERGE_DELAY);
+            if let Err(e) = merger.flush(before) {
+                merged = Err(e.to_string());
+                stop.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+        // Stops the devices' threads sending to the merger if it failed.
+        drop(receiver);
+
+        let reports: Vec<Result<SessionReport, String>> = threads
+            .into_iter()
+            .map(|thread| thread.join().expect("Device thread panicked."))
+            .collect();
+        merged.map(|()| reports)
+    })?;
+
+    let (written, notes) = merger.finish()?;
+    let mut failed = Vec::new();
+    for report in reports {
+        match report {
+            Ok(report) => print!("{}", report),
+            Err(e) => failed.push(e),
+        }
+    }
+    println!("Merged {} rows into {}", written, merged_path.display());
+    for note in notes {
+        println!("{}", note);
+    }
+
+    if !failed.is_empty() {
+        return Err(failed.join("\n").into());
+    }
+    Ok(())
+}
+
+fn write_mode(matches: &ArgMatches) -> WriteMode {
+    if matches.get_flag("append") {
+        WriteMode::Append
+    } else if matches.get_flag("force") {
+        WriteMode::Overwrite
+    } else {
+        WriteMode::New
+    }
+}
+
+/// Open the port or capture a device is read from.
+fn open_input(
+    matches: &ArgMatches,
+    device: &DeviceSpec,
+) -> Result<Box<dyn Source + Send>, Box<dyn Error>> {
+    Ok(match &device.input {
+        Input::Replay(path) => {
+            let speed = *matches
+                .get_one::<f64>("speed")
+                .expect("Speed has a default.");
+            Box::new(Replay::open(path, device.decoder.interval(), speed)?)
+        }
+        Input::Port { name, baud_rate } => Box::new(
+            SerialSource::open(name, *baud_rate, device.decoder.timeout())
+                .map_err(|e| format!("Failed to open port {}: {}", name, e))?,
+        ),
+    })
+}
+
+/// The files and broker a device's rows go to, other than the merged file.
+fn own_sinks(
+    matches: &ArgMatches,
+    device: &DeviceSpec,
+    clock: &SessionClock,
+) -> Result<Vec<Box<dyn Sink + Send>>, Box<dyn Error>> {
+    let options = RecorderOptions {
+        device: device.name.clone(),
+        output: format!("{}_data.csv", device.name).into(),
+        events: format!("{}_events.csv", device.name).into(),
+        timeout: device.decoder.timeout(),
+        interval: device.decoder.interval(),
+    };
+    let mut sinks = cli::sinks_from_matches(
+        matches,
+        &options,
+        &device.input.to_string(),
+        clock.started_at(),
+    )?;
+    if let Some(mqtt) = cli::mqtt_from_matches(matches)? {
+        sinks.push(Box::new(mqtt));
+    }
+    Ok(sinks)
+}
+
+/// Record one device from `input` to `device_sinks` on the shared `clock`
+/// until it ends or `stop` is set.
+fn record_device(
+    matches: &ArgMatches,
+    device: &DeviceSpec,
+    mut input: Box<dyn Source + Send>,
+    device_sinks: Vec<Box<dyn Sink + Send>>,
+    clock: SessionClock,
+    stop: &Arc<AtomicBool>,
+) -> Result<SessionReport, Box<dyn Error>> {
+    let source = device.input.to_string();
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+    for sink in device_sinks {
+        sinks.push(sink);
+    }
+
+    let (decoder, stages) = device.decoder.build();
+    let mut recorder = Recorder::with_clock(decoder, stages, sinks, &device.name, &source, clock)?;
+    recorder.set_label(&device.name);
+    recorder.set_stop_flag(Arc::clone(stop));
+    let intervals = *matches
+        .get_one::<u32>("stale-after")
+        .expect("Stale-after has a default.");
+    recorder.set_watchdog(Watchdog::new(device.decoder.interval(), intervals, false));
+    recorder.run(input.as_mut())
+}
+
+/// A row received from one of the devices.
+#[derive(Debug)]
+enum Row {
+    Sample(Vec<Value>),
+    Event(Event),
+}
+
+#[derive(Debug)]
+struct Entry {
+    device: usize,
+    received: HostTime,
+    row: Row,
+}
+
+/// Hands a device's rows to the merger.
+struct Forward {
+    device: usize,
+    sender: Sender<Entry>,
+}
+
+impl Forward {
+    fn send(&self, received: &HostTime, row: Row) -> Result<(), Box<dyn Error>> {
+        self.sender
+            .send(Entry {
+                device: self.device,
+                received: *received,
+                row,
+            })
+            .map_err(|_| "the merged output has stopped".into())
+    }
+}
+
+impl Sink for Forward {
+    fn open(&mut self, _session: &SessionInfo) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn write_sample(
+        &mut self,
+        received: &HostTime,
+        values: &[Value],
+    ) -> Result<(), Box<dyn Error>> {
+        self.send(received, Row::Sample(values.to_vec()))
+    }
+
+    fn write_event(&mut self, received: &HostTime, event: &Event) -> Result<(), Box<dyn Error>> {
+        self.send(received, Row::Event(event.clone()))
+    }
+}
+
+/// Writes the rows of every device to one CSV file in host time order.
+///
+/// After the device name, each device has its own columns, named after the
+/// device and left empty in the rows of the others. Events go to the merged
+/// events file with the device name in front of the detail.
+struct Merger {
+    sink: Box<dyn Sink>,
+    /// Name and first column of each device.
+    devices: Vec<(String, usize)>,
+    width: usize,
+    pending: Vec<Entry>,
+    written: u64,
+    clock: SessionClock,
+}
+
+impl Merger {
+    fn open(
+        devices: &[DeviceSpec],
+        path: &Path,
+        mode: WriteMode,
+        rotation: Option<&Rotation>,
+        clock: &SessionClock,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut headers = vec!["Device".to_string()];
+        let mut columns = Vec::new();
+        for device in devices {
+            let (decoder, stages) = device.decoder.build();
+            columns.push((device.name.clone(), headers.len()));
+            headers.extend(
+                decoder
+                    .headers()
+                    .into_iter()
+                    .chain(stages.iter().flat_map(|stage| stage.headers()))
+                    .map(|header| format!("{} {}", device.name, header)),
+            );
+        }
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let events = path.with_file_name(format!("{}_events.csv", stem));
+        let mut sink: Box<dyn Sink + Send> =
+            Box::new(CsvSink::new(path.to_path_buf(), events, mode));
+        if let Some(rotation) = rotation {
+            sink = cli::rotating(sink, rotation)?;
+        }
+        sink.open(&SessionInfo {
+            device: "merged".to_string(),
+            source: devices
+                .iter()
+                .map(|device| device.input.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            started_at: clock.started_at(),
+            headers: headers.clone(),
+        })?;
+
+        Ok(Merger {
+            sink,
+            devices: columns,
+            width: headers.len(),
+            pending: Vec::new(),
+            written: 0,
+            clock: *clock,
+        })
+    }
+
+    fn push(&mut self, entry: Entry) {
+        self.pending.push(entry);
+    }
+
+    /// Write every row received before `before`, oldest first.
+    fn flush(&mut self, before: Duration) -> Result<(), Box<dyn Error>> {
+        self.pending
+            .sort_by_key(|entry| (entry.received.elapsed, entry.device));
+        let ready = self
+            .pending
+            .partition_point(|entry| entry.received.elapsed < before);
+        let entries: Vec<Entry> = self.pending.drain(..ready).collect();
+        for entry in entries {
+            self.write(entry)?;
+        }
+        self.record_notices()
+    }
+
+    /// Record what the merged output's sink has noticed, e.g. rotated files,
+    /// in the merged events file.
+    fn record_notices(&mut self) -> Result<(), Box<dyn Error>> {
+        let received = self.clock.now();
+        for event in self.sink.notices() {
+            self.sink.write_event(&received, &event)?;
+        }
+        Ok(())
+    }
+
+    /// Write whatever is left and close the files, returning the number of
+    /// samples merged and anything noticed too late to record.
+    fn finish(&mut self) -> Result<(u64, Vec<String>), Box<dyn Error>> {
+        self.flush(Duration::MAX)?;
+        self.sink.close()?;
+        let notes = self.sink.notices().into_iter().map(|event| event.detail);
+        Ok((self.written, notes.collect()))
+    }
+
+    fn write(&mut self, entry: Entry) -> Result<(), Box<dyn Error>> {
+        let (name, first) = &self.devices[entry.device];
+        match entry.row {
+            Row::Sample(values) => {
+                let mut row = vec![Value::Text(String::new()); self.width];
+                row[0] = Value::Text(name.clone());
+                for (cell, value) in row[*first..].iter_mut().zip(values) {
+                    *cell = value;
+                }
+                self.written += 1;
+                self.sink.write_sample(&entry.received, &row)
+            }
+            Row::Event(event) => {
+                let detail = format!("{}: {}", name, event.detail);
+                self.sink
+                    .write_event(&entry.received, &Event::new(event.kind, detail))
+            }
+        }
+    }
+}
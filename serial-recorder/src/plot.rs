@@ -0,0 +1,368 @@
+//! Charts of a recorder's CSV output, so the figures for a report can be
+//! regenerated from the command line instead of by hand.
+//!
+//! Every charted column gets a panel of its own, one above the other on a
+//! shared time axis, with a line wherever the events file has an error.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use plotters::coord::Shift;
+use plotters::prelude::*;
+
+use crate::clock::HOST_TIME_HEADERS;
+use crate::events::EventKind;
+use crate::recorded::{self, Recording};
+
+/// Events marked on the charts: anything that means samples are missing or
+/// wrong around that time.
+const MARKED: [EventKind; 8] = [
+    EventKind::PinError,
+    EventKind::ChecksumMismatch,
+    EventKind::Timeout,
+    EventKind::Unparsed,
+    EventKind::InvalidUtf8,
+    EventKind::LineOverflow,
+    EventKind::Disconnected,
+    EventKind::Stale,
+];
+
+/// Colours of the charted columns, leaving red for the event markers.
+const COLORS: [RGBColor; 6] = [
+    RGBColor(31, 119, 180),
+    RGBColor(44, 160, 44),
+    RGBColor(148, 103, 189),
+    RGBColor(255, 127, 14),
+    RGBColor(140, 86, 75),
+    RGBColor(23, 190, 207),
+];
+
+/// Panel height when `--size` isn't given.
+const PANEL_HEIGHT: u32 = 250;
+
+/// The `plot` subcommand.
+pub fn command() -> Command {
+    Command::new("plot")
+        .about("Chart a CSV file written by a recorder as SVG or PNG.")
+        .arg(
+            Arg::new("data")
+                .help("The recorded samples.")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .help("Chart to write, as SVG or PNG by its extension. Defaults to DATA.svg.")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("channel")
+                .long("channel")
+                .value_name("COLUMN")
+                .help("Column to chart, may be repeated. Defaults to every numeric column.")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("events")
+                .long("events")
+                .value_name("FILE")
+                .help("Events to mark errors from. Defaults to the file written with DATA.")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("no-events")
+                .long("no-events")
+                .help("Don't mark any events.")
+                .conflicts_with("events")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("rolling-mean")
+                .long("rolling-mean")
+                .value_name("N")
+                .help("Also draw the mean of the last N samples.")
+                .value_parser(clap::value_parser!(u64).range(2..)),
+        )
+        .arg(
+            Arg::new("title")
+                .long("title")
+                .help("Title of the chart. Defaults to the data file's name."),
+        )
+        .arg(
+            Arg::new("size")
+                .long("size")
+                .value_name("WIDTHxHEIGHT")
+                .help("Size of the chart in pixels.")
+                .value_parser(parse_size),
+        )
+}
+
+/// Draw the chart described by the `plot` subcommand's `matches`.
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_path = matches
+        .get_one::<PathBuf>("data")
+        .expect("Data is required.");
+    let channels: Vec<String> = matches
+        .get_many::<String>("channel")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    let mut data = read_data(data_path, &channels)?;
+
+    if let Some(window) = matches.get_one::<u64>("rolling-mean") {
+        for series in data.series.iter_mut() {
+            series.mean = Some(rolling_mean(&series.points, *window as usize));
+        }
+        data.window = *window;
+    }
+
+    let events_path = match matches.get_one::<PathBuf>("events") {
+        Some(path) => Some(path.clone()),
+        None if matches.get_flag("no-events") => None,
+        None => recorded::events_path_for(data_path),
+    };
+    let events = match events_path {
+        Some(_) if !data.timed => {
+            println!(
+                "No session times in {}, so no events are marked",
+                data_path.display()
+            );
+            Vec::new()
+        }
+        Some(path) => read_events(&path)?,
+        None => Vec::new(),
+    };
+
+    let output = matches
+        .get_one::<PathBuf>("output")
+        .cloned()
+        .unwrap_or_else(|| data_path.with_extension("svg"));
+    let title = matches
+        .get_one::<String>("title")
+        .cloned()
+        .unwrap_or_else(|| {
+            data_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        });
+    let panels = data.series.len() as u32;
+    let size = matches
+        .get_one::<(u32, u32)>("size")
+        .copied()
+        .unwrap_or((1024, (PANEL_HEIGHT * panels + 60).max(400)));
+
+    match output.extension().and_then(|ext| ext.to_str()) {
+        Some("svg") => draw(
+            SVGBackend::new(&output, size).into_drawing_area(),
+            &title,
+            &data,
+            &events,
+        )?,
+        Some("png") => draw(
+            BitMapBackend::new(&output, size).into_drawing_area(),
+            &title,
+            &data,
+            &events,
+        )?,
+        _ => {
+            return Err(format!(
+                "Can't tell the format of {}; name it .svg or .png",
+                output.display()
+            )
+            .into())
+        }
+    }
+    println!("Chart written to {}", output.display());
+    Ok(())
+}
+
+/// Parse a size such as "1024x768".
+fn parse_size(text: &str) -> Result<(u32, u32), String> {
+    text.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|&(width, height)| width > 0 && height > 0)
+        .ok_or_else(|| format!("Invalid size {:?}, expected e.g. 1024x768", text))
+}
+
+/// One charted column.
+#[derive(Debug)]
+struct Series {
+    name: String,
+    points: Vec<(f64, f64)>,
+    mean: Option<Vec<(f64, f64)>>,
+}
+
+#[derive(Debug)]
+struct Data {
+    /// Whether x is the session time, rather than the row number for files
+    /// written before the recorders had timestamps.
+    timed: bool,
+    series: Vec<Series>,
+    window: u64,
+}
+
+/// Read `channels` from a recorder's CSV, or every numeric column.
+fn read_data(path: &Path, channels: &[String]) -> Result<Data, Box<dyn Error>> {
+    let recording = Recording::read(path, channels)?;
+    Ok(Data {
+        timed: recording.timed,
+        series: recording
+            .channels
+            .into_iter()
+            .map(|channel| Series {
+                name: channel.name,
+                points: channel.points,
+                mean: None,
+            })
+            .collect(),
+        window: 0,
+    })
+}
+
+/// Session times of the events worth marking.
+fn read_events(path: &Path) -> Result<Vec<f64>, Box<dyn Error>> {
+    let mut reader = csv::Reader::from_path(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| format!("No {:?} column in {}", name, path.display()))
+    };
+    let time = column(HOST_TIME_HEADERS[1])?;
+    let kind = column("Event")?;
+
+    let mut times = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let marked = record
+            .get(kind)
+            .is_some_and(|name| MARKED.iter().any(|kind| kind.name() == name));
+        if let Some(t) = record.get(time).and_then(|t| t.parse().ok()) {
+            if marked {
+                times.push(t);
+            }
+        }
+    }
+    Ok(times)
+}
+
+/// The mean of each point and the `window - 1` points before it.
+fn rolling_mean(points: &[(f64, f64)], window: usize) -> Vec<(f64, f64)> {
+    let mut sum = 0.0;
+    points
+        .iter()
+        .enumerate()
+        .map(|(i, &(x, y))| {
+            sum += y;
+            if i >= window {
+                sum -= points[i - window].1;
+            }
+            (x, sum / (i + 1).min(window) as f64)
+        })
+        .collect()
+}
+
+/// The range of `values`, padded a little so lines don't run along the edges.
+fn padded_range(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+        (min.min(v), max.max(v))
+    });
+    let pad = if max > min { (max - min) * 0.05 } else { 1.0 };
+    (min - pad, max + pad)
+}
+
+fn draw<DB>(
+    root: DrawingArea<DB, Shift>,
+    title: &str,
+    data: &Data,
+    events: &[f64],
+) -> Result<(), Box<dyn Error>>
+where
+    DB: DrawingBackend,
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    let root = root.titled(title, ("sans-serif", 24))?;
+    let panels = root.split_evenly((data.series.len(), 1));
+
+    let (x_min, x_max) = {
+        let xs = data
+            .series
+            .iter()
+            .flat_map(|s| s.points.iter().map(|p| p.0));
+        let (min, max) = padded_range(xs.clone());
+        // Time starts at zero; only pad the far end.
+        (xs.fold(f64::INFINITY, f64::min).max(min), max)
+    };
+    let x_label = if data.timed {
+        HOST_TIME_HEADERS[1]
+    } else {
+        "Sample"
+    };
+    let events: Vec<f64> = events
+        .iter()
+        .copied()
+        .filter(|t| (x_min..=x_max).contains(t))
+        .collect();
+
+    for (i, (panel, series)) in panels.iter().zip(&data.series).enumerate() {
+        let last = i + 1 == data.series.len();
+        let (y_min, y_max) = padded_range(series.points.iter().map(|p| p.1));
+        let mut chart = ChartBuilder::on(panel)
+            .margin(10)
+            .x_label_area_size(if last { 40 } else { 20 })
+            .y_label_area_size(70)
+            .build_cartesian_2d(x_min..x_max, y_min..y_max)?;
+
+        let mut mesh = chart.configure_mesh();
+        mesh.y_desc(series.name.as_str());
+        if last {
+            mesh.x_desc(x_label);
+        }
+        mesh.draw()?;
+
+        if !events.is_empty() {
+            let marker = RED.mix(0.5);
+            chart
+                .draw_series(
+                    events
+                        .iter()
+                        .map(|&t| PathElement::new(vec![(t, y_min), (t, y_max)], marker)),
+                )?
+                .label(format!("errors ({})", events.len()))
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], marker));
+        }
+
+        let color = COLORS[i % COLORS.len()];
+        chart
+            .draw_series(LineSeries::new(series.points.iter().copied(), color))?
+            .label(series.name.as_str())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+
+        if let Some(mean) = &series.mean {
+            let style = BLACK.stroke_width(2);
+            chart
+                .draw_series(LineSeries::new(mean.iter().copied(), style))?
+                .label(format!("mean of {}", data.window))
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style));
+        }
+
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+    }
+
+    root.present()?;
+    Ok(())
+}
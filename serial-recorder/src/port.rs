@@ -0,0 +1,244 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::sleep;
+use std::time::Duration;
+
+use serialport::{SerialPort, SerialPortInfo, SerialPortType, UsbPortInfo};
+
+use crate::source::Source;
+
+const FIRST_RETRY: Duration = Duration::from_millis(500);
+const MAX_RETRY: Duration = Duration::from_secs(10);
+/// How often a stop request is checked for while waiting to reconnect.
+const STOP_CHECK: Duration = Duration::from_millis(100);
+
+/// A live serial port that reopens itself if the device goes away.
+///
+/// The device is remembered by its path and, for USB adapters, its serial
+/// number, so it's found again even if it comes back under another name.
+pub struct SerialSource {
+    path: String,
+    baud_rate: u32,
+    timeout: Duration,
+    serial_number: Option<String>,
+    /// `None` while the device is gone.
+    port: Option<Box<dyn SerialPort>>,
+}
+
+impl SerialSource {
+    pub fn open(path: &str, baud_rate: u32, timeout: Duration) -> serialport::Result<Self> {
+        let port = serialport::new(path, baud_rate).timeout(timeout).open()?;
+
+        Ok(SerialSource {
+            path: path.to_string(),
+            baud_rate,
+            timeout,
+            serial_number: usb_serial_number(path),
+            port: Some(port),
+        })
+    }
+
+    /// Try to open the device again wherever a port with the same USB serial
+    /// number shows up, or at its old path if it hasn't got one. Another board
+    /// that takes over the old path isn't mistaken for it.
+    fn reopen(&mut self) -> Option<String> {
+        // With exclusive access, the old handle would keep the port busy.
+        self.port = None;
+        let candidates = match &self.serial_number {
+            Some(serial_number) => serialport::available_ports()
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|port| match &port.port_type {
+                    SerialPortType::UsbPort(info) => {
+                        info.serial_number.as_ref() == Some(serial_number)
+                    }
+                    _ => false,
+                })
+                .map(|port| port.port_name)
+                .collect(),
+            None => vec![self.path.clone()],
+        };
+
+        for path in candidates {
+            if let Ok(port) = serialport::new(&path, self.baud_rate)
+                .timeout(self.timeout)
+                .open()
+            {
+                self.port = Some(port);
+                self.path = path;
+                return Some(self.path.clone());
+            }
+        }
+        None
+    }
+
+    fn connected(&mut self) -> io::Result<&mut Box<dyn SerialPort>> {
+        self.port
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))
+    }
+}
+
+impl Read for SerialSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.connected()?.read(buf)
+    }
+}
+
+impl Source for SerialSource {
+    fn recover(&mut self, _error: &io::Error, stop: &AtomicBool) -> Option<String> {
+        let mut delay = FIRST_RETRY;
+        loop {
+            // Sleep in short steps so a stop request isn't held up by the backoff.
+            let mut waited = Duration::ZERO;
+            while waited < delay {
+                if stop.load(Ordering::Relaxed) {
+                    return None;
+                }
+                sleep(STOP_CHECK);
+                waited += STOP_CHECK;
+            }
+            if let Some(path) = self.reopen() {
+                return Some(path);
+            }
+            delay = (delay * 2).min(MAX_RETRY);
+        }
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let port = self.connected()?;
+        port.write_all(bytes)?;
+        port.flush()
+    }
+
+    fn reconnects(&self) -> bool {
+        true
+    }
+}
+
+/// The USB serial number of the adapter at `path`, if it has one.
+fn usb_serial_number(path: &str) -> Option<String> {
+    serialport::available_ports()
+        .ok()?
+        .into_iter()
+        .find(|port| port.port_name == path)
+        .and_then(|port| match port.port_type {
+            SerialPortType::UsbPort(info) => info.serial_number,
+            _ => None,
+        })
+}
+
+/// Picks a USB serial adapter by vendor and product ID, and optionally its
+/// serial number, written as `vid:pid[:serial]` in hex (e.g. `2341:0043`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortMatch {
+    pub vid: u16,
+    pub pid: u16,
+    pub serial_number: Option<String>,
+}
+
+impl PortMatch {
+    pub fn matches(&self, info: &UsbPortInfo) -> bool {
+        info.vid == self.vid
+            && info.pid == self.pid
+            && self
+                .serial_number
+                .as_ref()
+                .is_none_or(|serial| info.serial_number.as_ref() == Some(serial))
+    }
+
+    /// Find the one connected port this matches.
+    pub fn find(&self) -> Result<String, Box<dyn Error>> {
+        let found: Vec<SerialPortInfo> = serialport::available_ports()?
+            .into_iter()
+            .filter(|port| match &port.port_type {
+                SerialPortType::UsbPort(info) => self.matches(info),
+                _ => false,
+            })
+            .collect();
+
+        match found.as_slice() {
+            [port] => Ok(port.port_name.clone()),
+            [] => Err(format!("No serial port matches {}", self).into()),
+            _ => Err(format!(
+                "{} ports match {}: {}. Add the serial number to pick one.",
+                found.len(),
+                self,
+                found
+                    .iter()
+                    .map(|port| port.port_name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into()),
+        }
+    }
+}
+
+impl FromStr for PortMatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let mut hex = |name: &str| {
+            let part = parts.next().filter(|part| !part.is_empty());
+            let part = part
+                .ok_or_else(|| format!("Missing {} in {:?}, expected vid:pid[:serial]", name, s))?;
+            u16::from_str_radix(part, 16)
+                .map_err(|_| format!("Invalid {} {:?}, expected hex", name, part))
+        };
+        let vid = hex("vendor ID")?;
+        let pid = hex("product ID")?;
+
+        Ok(PortMatch {
+            vid,
+            pid,
+            serial_number: parts.next().map(str::to_string),
+        })
+    }
+}
+
+impl fmt::Display for PortMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vid, self.pid)?;
+        if let Some(serial) = &self.serial_number {
+            write!(f, ":{}", serial)?;
+        }
+        Ok(())
+    }
+}
+
+/// Print every serial port the system knows about, with USB details.
+pub fn list_ports() -> Result<(), Box<dyn Error>> {
+    let ports = serialport::available_ports()?;
+    if ports.is_empty() {
+        println!("No serial ports found.");
+        return Ok(());
+    }
+
+    println!(
+        "{:<20} {:<10} {:<10} {:<24} {:<20} PRODUCT",
+        "PORT", "TYPE", "VID:PID", "SERIAL", "MANUFACTURER"
+    );
+    for port in ports {
+        match port.port_type {
+            SerialPortType::UsbPort(info) => println!(
+                "{:<20} {:<10} {:04x}:{:04x}  {:<24} {:<20} {}",
+                port.port_name,
+                "usb",
+                info.vid,
+                info.pid,
+                info.serial_number.as_deref().unwrap_or("-"),
+                info.manufacturer.as_deref().unwrap_or("-"),
+                info.product.as_deref().unwrap_or("-"),
+            ),
+            SerialPortType::PciPort => println!("{:<20} pci", port.port_name),
+            SerialPortType::BluetoothPort => println!("{:<20} bluetooth", port.port_name),
+            SerialPortType::Unknown => println!("{:<20} unknown", port.port_name),
+        }
+    }
+    Ok(())
+}
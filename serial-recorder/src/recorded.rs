@@ -0,0 +1,111 @@
+//! Reading back the CSV files the recorders write, for the subcommands that
+//! work on a finished recording.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::clock::HOST_TIME_HEADERS;
+use crate::sinks::field_name;
+
+/// The numeric values of one column, against session time in seconds, or
+/// against the row number if the file has no session times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    pub name: String,
+    pub points: Vec<(f64, f64)>,
+}
+
+/// The columns read from a recorder's CSV file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recording {
+    /// Whether x is the session time, rather than the row number for files
+    /// written before the recorders had timestamps.
+    pub timed: bool,
+    pub channels: Vec<Channel>,
+}
+
+impl Recording {
+    /// Read `channels` from a recorder's CSV, or every numeric column if
+    /// none are named. Cells that aren't numbers, such as the other devices'
+    /// empty cells in a merged file, are skipped.
+    pub fn read(path: &Path, channels: &[String]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = csv::Reader::from_path(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let headers: Vec<String> = reader.headers()?.iter().map(str::to_string).collect();
+        let time_column = headers.iter().position(|h| h == HOST_TIME_HEADERS[1]);
+
+        let columns: Vec<usize> = if channels.is_empty() {
+            (0..headers.len())
+                .filter(|i| !HOST_TIME_HEADERS.contains(&headers[*i].as_str()))
+                .collect()
+        } else {
+            channels
+                .iter()
+                .map(|channel| {
+                    headers
+                        .iter()
+                        .position(|h| h == channel || field_name(h) == field_name(channel))
+                        .ok_or_else(|| {
+                            format!(
+                                "No column {:?} in {}, it has {}",
+                                channel,
+                                path.display(),
+                                headers.join(", ")
+                            )
+                        })
+                })
+                .collect::<Result<_, _>>()?
+        };
+
+        let mut points = vec![Vec::new(); columns.len()];
+        for (row, record) in reader.records().enumerate() {
+            let record = record?;
+            let x = match time_column {
+                Some(column) => match record.get(column).and_then(|t| t.parse().ok()) {
+                    Some(x) => x,
+                    None => continue,
+                },
+                None => row as f64,
+            };
+            for (points, &column) in points.iter_mut().zip(&columns) {
+                if let Some(y) = record
+                    .get(column)
+                    .and_then(|v| v.trim().parse::<f64>().ok())
+                {
+                    points.push((x, y));
+                }
+            }
+        }
+
+        let mut series = Vec::new();
+        for (points, column) in points.into_iter().zip(columns) {
+            let name = headers[column].clone();
+            if points.is_empty() {
+                if channels.is_empty() {
+                    continue;
+                }
+                return Err(format!("Column {:?} has no numbers in it", name).into());
+            }
+            series.push(Channel { name, points });
+        }
+        if series.is_empty() {
+            return Err(format!("No numeric columns in {}", path.display()).into());
+        }
+        Ok(Recording {
+            timed: time_column.is_some(),
+            channels: series,
+        })
+    }
+}
+
+/// The events file a recorder writes next to `data`: `rpm_events.csv` for
+/// `rpm_data.csv`, or `run_events.csv` for an `--output` of `run.csv`.
+pub fn events_path_for(data: &Path) -> Option<PathBuf> {
+    let stem = data.file_stem()?.to_string_lossy();
+    let mut candidates = Vec::new();
+    if let Some(base) = stem.strip_suffix("_data") {
+        candidates.push(data.with_file_name(format!("{}_events.csv", base)));
+    }
+    candidates.push(data.with_file_name(format!("{}_events.csv", stem)));
+    candidates.into_iter().find(|path| path.is_file())
+}
@@ -0,0 +1,413 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::{HostTime, SessionClock};
+use crate::commands::CommandChannel;
+use crate::decoder::LineDecoder;
+use crate::events::{Event, EventCounts, EventKind};
+use crate::framer::{Frame, LineFramer};
+use crate::report::{SessionReport, SessionStats};
+use crate::sinks::{SessionInfo, Sink};
+use crate::source::Source;
+use crate::stage::Stage;
+use crate::watchdog::Watchdog;
+
+/// Per-device settings that the individual recorder binaries choose.
+#[derive(Debug, Clone)]
+pub struct RecorderOptions {
+    /// Short name for the instrument, used to label output.
+    pub device: String,
+    /// CSV file the decoded rows are written to.
+    pub output: PathBuf,
+    /// CSV file for error messages and lines that couldn't be decoded.
+    pub events: PathBuf,
+    /// Read timeout for the serial port.
+    pub timeout: Duration,
+    /// How often the device sends a line; used to pace replays and to notice
+    /// when the device goes quiet.
+    pub interval: Duration,
+}
+
+/// Longest line accepted from a device. The sketches print a few dozen bytes
+/// at most, so anything longer is line noise or a missing terminator.
+pub const MAX_LINE_LENGTH: usize = 256;
+
+/// Splits incoming bytes into lines, decodes them and hands the rows to sinks.
+pub struct Recorder {
+    decoder: Box<dyn LineDecoder>,
+    stages: Vec<Box<dyn Stage>>,
+    sinks: Vec<Box<dyn Sink>>,
+    events: EventCounts,
+    clock: SessionClock,
+    framer: LineFramer,
+    session: SessionInfo,
+    stats: SessionStats,
+    quiet: bool,
+    label: Option<String>,
+    stop: Arc<AtomicBool>,
+    watchdog: Option<Watchdog>,
+    commands: Option<CommandChannel>,
+}
+
+impl Recorder {
+    /// Start a session for `device` reading from `source`, and open every sink
+    /// with the decoder's and stages' headers.
+    pub fn new(
+        decoder: Box<dyn LineDecoder>,
+        stages: Vec<Box<dyn Stage>>,
+        sinks: Vec<Box<dyn Sink>>,
+        device: &str,
+        source: &str,
+    ) -> Result<Self, Box<dyn Error>> {
+        Recorder::with_clock(
+            decoder,
+            stages,
+            sinks,
+            device,
+            source,
+            SessionClock::start(),
+        )
+    }
+
+    /// Like [`Recorder::new`], but timing the session with an existing
+    /// `clock`, so that several recorders share one timeline.
+    pub fn with_clock(
+        decoder: Box<dyn LineDecoder>,
+        stages: Vec<Box<dyn Stage>>,
+        mut sinks: Vec<Box<dyn Sink>>,
+        device: &str,
+        source: &str,
+        clock: SessionClock,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut headers = decoder.headers();
+        headers.extend(stages.iter().flat_map(|stage| stage.headers()));
+        let session = SessionInfo {
+            device: device.to_string(),
+            source: source.to_string(),
+            started_at: clock.started_at(),
+            headers,
+        };
+        for sink in sinks.iter_mut() {
+            sink.open(&session)?;
+        }
+
+        Ok(Recorder {
+            decoder,
+            stages,
+            sinks,
+            events: EventCounts::default(),
+            clock,
+            framer: LineFramer::new(MAX_LINE_LENGTH),
+            stats: SessionStats::new(&session.headers),
+            session,
+            quiet: false,
+            label: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            watchdog: None,
+            commands: None,
+        })
+    }
+
+    /// Stop printing samples and events to standard output, e.g. because a
+    /// dashboard has taken over the terminal.
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    /// Start everything printed to standard output with `label`, to tell
+    /// several recorders sharing a terminal apart.
+    pub fn set_label(&mut self, label: &str) {
+        self.label = Some(label.to_string());
+    }
+
+    /// End the recording cleanly once `stop` is set, e.g. from another thread.
+    pub fn set_stop_flag(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = stop;
+    }
+
+    /// Watch for the device going quiet, recording an event when it does and
+    /// optionally ending the recording.
+    pub fn set_watchdog(&mut self, watchdog: Watchdog) {
+        self.watchdog = Some(watchdog);
+    }
+
+    /// Send `commands` to the device in between reads, matching their replies.
+    pub fn set_commands(&mut self, commands: CommandChannel) {
+        self.commands = Some(commands);
+    }
+
+    /// Read from `source` until it runs out, recording every line it produces,
+    /// and report on the session.
+    ///
+    /// A serial port never runs out, so for a live device this loops until the
+    /// stop flag is set, reconnecting whenever the port goes away.
+    pub fn run(&mut self, source: &mut dyn Source) -> Result<SessionReport, Box<dyn Error>> {
+        let mut read_buffer: [u8; 128] = [0; 128];
+
+        loop {
+            self.check_watchdog()?;
+            self.send_commands(source)?;
+            self.record_notices()?;
+            if self.stop.load(Ordering::Relaxed) {
+                return self.finish();
+            }
+
+            let error = match source.read(read_buffer.as_mut_slice()) {
+                Ok(0) => io::Error::from(io::ErrorKind::UnexpectedEof),
+                Ok(bytes_read) => {
+                    self.feed(&read_buffer[..bytes_read])?;
+                    continue;
+                }
+                Err(ref e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => e,
+            };
+
+            // A signal arriving mid-read can surface as an error of its own.
+            if self.stop.load(Ordering::Relaxed) {
+                return self.finish();
+            }
+
+            // Replayed input ends by running out; a live device that does is
+            // as gone as one whose reads fail.
+            let lost = self.clock.now();
+            if error.kind() != io::ErrorKind::UnexpectedEof || source.reconnects() {
+                self.say(format_args!("Lost {}: {}", self.session.source, error));
+                self.record_event(
+                    &lost,
+                    &Event::new(EventKind::Disconnected, error.to_string()),
+                )?;
+            }
+
+            let Some(reconnected_to) = source.recover(&error, &self.stop) else {
+                return self.finish();
+            };
+
+            // Whatever was left of a line before the gap can't be completed.
+            self.framer.clear();
+
+            let now = self.clock.now();
+            if let Some(watchdog) = self.watchdog.as_mut() {
+                watchdog.reset(now.elapsed);
+            }
+            let gap = now.elapsed - lost.elapsed;
+            let detail = format!(
+                "reconnected to {} after {:.1} s",
+                reconnected_to,
+                gap.as_secs_f64()
+            );
+            self.say(&detail);
+            self.record_event(&now, &Event::new(EventKind::Reconnected, detail))?;
+        }
+    }
+
+    /// Append raw bytes from the device and process every line they complete.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        for frame in self.framer.push(bytes) {
+            self.handle_frame(frame)?;
+        }
+        Ok(())
+    }
+
+    /// Process whatever is still buffered once the input has ended, close the
+    /// sinks and report on the session.
+    pub fn finish(&mut self) -> Result<SessionReport, Box<dyn Error>> {
+        if let Some(frame) = self.framer.finish() {
+            self.handle_frame(frame)?;
+        }
+        self.record_notices()?;
+
+        for sink in self.sinks.iter_mut() {
+            sink.close()?;
+        }
+
+        // Whatever the sinks had to say while closing can't be recorded any
+        // more, so it goes in the report.
+        let mut notes: Vec<String> = self
+            .stages
+            .iter()
+            .filter_map(|stage| stage.summary())
+            .collect();
+        for sink in self.sinks.iter_mut() {
+            notes.extend(sink.notices().into_iter().map(|event| event.detail));
+        }
+        Ok(self
+            .stats
+            .report(&self.session, self.clock.now().elapsed, &self.events, notes))
+    }
+
+    /// Per-kind counts of the events seen so far.
+    pub fn events(&self) -> &EventCounts {
+        &self.events
+    }
+
+    fn say(&self, message: impl Display) {
+        match (&self.label, self.quiet) {
+            (_, true) => {}
+            (Some(label), false) => println!("[{}] {}", label, message),
+            (None, false) => println!("{}", message),
+        }
+    }
+
+    /// Record and report what the sinks have noticed about themselves.
+    fn record_notices(&mut self) -> Result<(), Box<dyn Error>> {
+        let notices: Vec<Event> = self
+            .sinks
+            .iter_mut()
+            .flat_map(|sink| sink.notices())
+            .collect();
+        let received = self.clock.now();
+        for event in notices {
+            self.say(&event.detail);
+            self.record_event(&received, &event)?;
+        }
+        Ok(())
+    }
+
+    fn send_commands(&mut self, source: &mut dyn Source) -> Result<(), Box<dyn Error>> {
+        let now = self.clock.now();
+        let Some(commands) = self.commands.as_mut() else {
+            return Ok(());
+        };
+
+        let mut events = Vec::new();
+        events.extend(commands.check_timeout(now.elapsed));
+        while let Some(command) = commands.next(now.elapsed) {
+            let line = format!("{}\n", command.text);
+            events.push(match source.send(line.as_bytes()) {
+                Ok(()) => Event::new(EventKind::CommandSent, command.text),
+                Err(e) => {
+                    commands.cancel();
+                    Event::new(
+                        EventKind::CommandFailed,
+                        format!("{:?}: {}", command.text, e),
+                    )
+                }
+            });
+        }
+
+        for event in events {
+            self.say(format_args!("Event {}: {}", event.kind, event.detail));
+            self.record_event(&now, &event)?;
+        }
+        Ok(())
+    }
+
+    fn check_watchdog(&mut self) -> Result<(), Box<dyn Error>> {
+        let now = self.clock.now();
+        let Some(watchdog) = self.watchdog.as_mut() else {
+            return Ok(());
+        };
+        let Some(event) = watchdog.check(now.elapsed) else {
+            return Ok(());
+        };
+        if watchdog.stops_recording() {
+            self.stop.store(true, Ordering::Relaxed);
+        }
+        self.say(format_args!("Event {}: {}", event.kind, event.detail));
+        self.record_event(&now, &event)
+    }
+
+    fn record_event(&mut self, received: &HostTime, event: &Event) -> Result<(), Box<dyn Error>> {
+        self.events.record(event.kind);
+        for sink in self.sinks.iter_mut() {
+            sink.write_event(received, event)?;
+        }
+        Ok(())
+    }
+
+    fn handle_frame(&mut self, frame: Frame) -> Result<(), Box<dyn Error>> {
+        match frame {
+            // Blank lines carry nothing to decode.
+            Frame::Line(line) if line.is_empty() => Ok(()),
+            Frame::Line(line) => self.handle_line(&line),
+            Frame::Overflow { discarded } => {
+                let detail = format!(
+                    "discarded {} bytes without a line ending (limit {})",
+                    discarded, MAX_LINE_LENGTH
+                );
+                self.say(format_args!("Line too long, {}", detail));
+                let received = self.clock.now();
+                self.record_event(&received, &Event::new(EventKind::LineOverflow, detail))
+            }
+        }
+    }
+
+    fn handle_line(&mut self, line: &[u8]) -> Result<(), Box<dyn Error>> {
+        let received = self.clock.now();
+
+        let Ok(line_str) = std::str::from_utf8(line) else {
+            let event = Event::new(EventKind::InvalidUtf8, String::from_utf8_lossy(line));
+            self.record_event(&received, &event)?;
+            self.say(format_args!(
+                "Failed from_utf8! ({} so far)",
+                self.events.count(EventKind::InvalidUtf8)
+            ));
+            return Ok(());
+        };
+
+        if let Some(event) = self
+            .commands
+            .as_mut()
+            .and_then(|commands| commands.reply(line_str, received.elapsed))
+        {
+            self.say(format_args!("Event {}: {}", event.kind, event.detail));
+            return self.record_event(&received, &event);
+        }
+
+        match self.decoder.decode(line_str) {
+            Ok(mut values) => {
+                self.events.count_sample();
+                if let Some(event) = self
+                    .watchdog
+                    .as_mut()
+                    .and_then(|watchdog| watchdog.sample(received.elapsed))
+                {
+                    self.say(format_args!("Event {}: {}", event.kind, event.detail));
+                    self.record_event(&received, &event)?;
+                }
+
+                // Write to standard output
+                self.say(self.decoder.describe(&values));
+
+                let mut events = Vec::new();
+                for stage in self.stages.iter_mut() {
+                    events.extend(stage.process(&received, &mut values));
+                }
+                for event in events {
+                    self.record_event(&received, &event)?;
+                    self.say(format_args!("Event {}: {}", event.kind, event.detail));
+                }
+                let progress: Vec<String> = self
+                    .stages
+                    .iter_mut()
+                    .filter_map(|stage| stage.progress())
+                    .collect();
+                for message in progress {
+                    self.say(message);
+                }
+
+                self.stats.add(&values);
+                for sink in self.sinks.iter_mut() {
+                    sink.write_sample(&received, &values)?;
+                }
+            }
+            Err(e) => {
+                self.record_event(&received, &Event::new(e.kind, e.detail()))?;
+                self.say(format_args!("{} ({} so far)", e, self.events.count(e.kind)));
+            }
+        }
+        Ok(())
+    }
+}
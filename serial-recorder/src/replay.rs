@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::source::Source;
+
+/// Plays back a raw serial capture as if it were arriving from the device.
+///
+/// Each line of the capture is released after `interval / speed`, so a speed of
+/// 1.0 reproduces the device's original pacing and larger values fast-forward.
+/// A speed of 0 replays the whole file without waiting.
+pub struct Replay {
+    reader: BufReader<File>,
+    delay: Duration,
+    pending: Vec<u8>,
+    offset: usize,
+    started: bool,
+}
+
+impl Replay {
+    pub fn open(path: &Path, interval: Duration, speed: f64) -> io::Result<Self> {
+        let delay = if speed > 0.0 {
+            interval.div_f64(speed)
+        } else {
+            Duration::ZERO
+        };
+
+        Ok(Replay {
+            reader: BufReader::new(File::open(path)?),
+            delay,
+            pending: Vec::new(),
+            offset: 0,
+            started: false,
+        })
+    }
+}
+
+impl Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset == self.pending.len() {
+            self.pending.clear();
+            self.offset = 0;
+            if self.reader.read_until(b'\n', &mut self.pending)? == 0 {
+                return Ok(0);
+            }
+
+            // The first line goes out immediately, just like a freshly opened port.
+            if self.started {
+                sleep(self.delay);
+            }
+            self.started = true;
+        }
+
+        let count = buf.len().min(self.pending.len() - self.offset);
+        buf[..count].copy_from_slice(&self.pending[self.offset..self.offset + count]);
+        self.offset += count;
+        Ok(count)
+    }
+}
+
+impl Source for Replay {}
+
+/// Slowest and fastest replay speeds, besides 0 for no waiting at all.
+const SPEEDS: (f64, f64) = (0.001, 1000.0);
+
+/// Parse a `--speed` multiplier: 0, or a speed between 0.001 and 1000.
+pub fn parse_speed(text: &str) -> Result<f64, String> {
+    let (slowest, fastest) = SPEEDS;
+    text.trim()
+        .parse()
+        .ok()
+        .filter(|speed: &f64| *speed == 0.0 || (slowest..=fastest).contains(speed))
+        .ok_or_else(|| {
+            format!(
+                "Invalid speed {:?}, expected 0 or from {} to {}",
+                text, slowest, fastest
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn parses_speeds() {
+        assert_eq!(parse_speed("0"), Ok(0.0));
+        assert_eq!(parse_speed("2.5"), Ok(2.5));
+        assert_eq!(parse_speed("1000"), Ok(1000.0));
+        for speed in ["1e-300", "-1", "1001", "inf", "NaN", "fast"] {
+            assert!(parse_speed(speed).is_err(), "{}", speed);
+        }
+    }
+
+    #[test]
+    fn replays_a_capture_quickly() {
+        let path =
+            std::env::temp_dir().join(format!("serial-recorder-replay-{}.cap", std::process::id()));
+        let capture = "1000,72.5\r\n2000,72.6\r\nTimeout!\r\n4000,72.8\r\n";
+        fs::write(&path, capture).unwrap();
+
+        let started = Instant::now();
+        let mut replay = Replay::open(&path, Duration::from_secs(1), 1000.0).unwrap();
+        let mut replayed = String::new();
+        replay.read_to_string(&mut replayed).unwrap();
+
+        assert_eq!(replayed, capture);
+        // Three waits of 1 ms each, rather than the three seconds it took.
+        let took = started.elapsed();
+        assert!(took >= Duration::from_millis(3), "{:?}", took);
+        assert!(took < Duration::from_secs(1), "{:?}", took);
+        fs::remove_file(&path).unwrap();
+    }
+}
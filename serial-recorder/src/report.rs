@@ -0,0 +1,287 @@
+//! End-of-session summary: how long the recording ran, what was lost and the
+//! distribution of every numeric channel.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Map};
+
+use crate::decoder::Value;
+use crate::events::{EventCounts, EventKind};
+use crate::sinks::SessionInfo;
+
+/// Percentiles listed for every channel.
+pub const PERCENTILES: [f64; 4] = [50.0, 90.0, 95.0, 99.0];
+
+/// Events for lines that never became samples.
+const DROPPED: [EventKind; 3] = [
+    EventKind::Unparsed,
+    EventKind::InvalidUtf8,
+    EventKind::LineOverflow,
+];
+
+/// Collects every numeric value of a session for the report.
+///
+/// All values are kept so the percentiles are exact; at the rates these
+/// devices send, a day of recording is a few megabytes.
+#[derive(Debug)]
+pub struct SessionStats {
+    headers: Vec<String>,
+    values: Vec<Vec<f64>>,
+}
+
+impl SessionStats {
+    pub fn new(headers: &[String]) -> Self {
+        SessionStats {
+            headers: headers.to_vec(),
+            values: vec![Vec::new(); headers.len()],
+        }
+    }
+
+    pub fn add(&mut self, values: &[Value]) {
+        for (column, value) in self.values.iter_mut().zip(values) {
+            if let Some(value) = value.as_f64() {
+                column.push(value);
+            }
+        }
+    }
+
+    /// Summarise the session so far.
+    pub fn report(
+        &self,
+        session: &SessionInfo,
+        duration: Duration,
+        events: &EventCounts,
+        notes: Vec<String>,
+    ) -> SessionReport {
+        let channels = self
+            .headers
+            .iter()
+            .zip(&self.values)
+            .filter_map(|(name, values)| ChannelStats::from_values(name, values))
+            .collect();
+
+        SessionReport {
+            device: session.device.clone(),
+            source: session.source.clone(),
+            started_at: session.started_at,
+            duration,
+            samples: events.samples(),
+            dropped: DROPPED.iter().map(|&kind| events.count(kind)).sum(),
+            event_summary: events.summary(),
+            events: events.counts().clone(),
+            channels,
+            notes,
+        }
+    }
+}
+
+/// Distribution of one numeric column over a session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelStats {
+    pub name: String,
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Sample standard deviation; zero for a single value.
+    pub stddev: f64,
+    /// The value at each of [`PERCENTILES`], in the same order.
+    pub percentiles: Vec<f64>,
+}
+
+impl ChannelStats {
+    /// `None` if the column never had a numeric value.
+    pub fn from_values(name: &str, values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let count = values.len();
+        let mean = values.iter().sum::<f64>() / count as f64;
+        let variance = if count > 1 {
+            values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (count - 1) as f64
+        } else {
+            0.0
+        };
+
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+
+        Some(ChannelStats {
+            name: name.to_string(),
+            count,
+            min: sorted[0],
+            max: sorted[count - 1],
+            mean,
+            stddev: variance.sqrt(),
+            percentiles: PERCENTILES
+                .iter()
+                .map(|&p| percentile(&sorted, p))
+                .collect(),
+        })
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let percentiles: Map<String, serde_json::Value> = PERCENTILES
+            .iter()
+            .zip(&self.percentiles)
+            .map(|(p, value)| (format!("p{}", p), json!(value)))
+            .collect();
+        json!({
+            "name": self.name,
+            "count": self.count,
+            "min": self.min,
+            "max": self.max,
+            "mean": self.mean,
+            "stddev": self.stddev,
+            "percentiles": percentiles,
+        })
+    }
+}
+
+/// The `p`th percentile of `sorted`, interpolating between neighbouring values.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let below = rank.floor() as usize;
+    let above = rank.ceil() as usize;
+    sorted[below] + (sorted[above] - sorted[below]) * (rank - below as f64)
+}
+
+/// The middle of `values`, which are sorted in place, or `None` if there
+/// aren't any.
+pub fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        Some((values[mid - 1] + values[mid]) / 2.0)
+    } else {
+        Some(values[mid])
+    }
+}
+
+/// What's printed, and optionally written, when a recording ends.
+#[derive(Debug, Clone)]
+pub struct SessionReport {
+    pub device: String,
+    pub source: String,
+    pub started_at: DateTime<Utc>,
+    pub duration: Duration,
+    pub samples: u64,
+    /// Lines that couldn't be turned into samples: unparsed, invalid UTF-8 or
+    /// too long.
+    pub dropped: u64,
+    pub event_summary: String,
+    pub events: BTreeMap<EventKind, u64>,
+    pub channels: Vec<ChannelStats>,
+    /// Closing remarks from the stages, such as the final drift estimate.
+    pub notes: Vec<String>,
+}
+
+impl SessionReport {
+    pub fn to_json(&self) -> serde_json::Value {
+        let events: Map<String, serde_json::Value> = self
+            .events
+            .iter()
+            .map(|(kind, count)| (kind.name().to_string(), json!(count)))
+            .collect();
+        let channels: Vec<serde_json::Value> =
+            self.channels.iter().map(ChannelStats::to_json).collect();
+
+        json!({
+            "device": self.device,
+            "source": self.source,
+            "started_at": self.started_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "duration_s": self.duration.as_secs_f64(),
+            "samples": self.samples,
+            "dropped_lines": self.dropped,
+            "events": events,
+            "channels": channels,
+            "notes": self.notes,
+        })
+    }
+
+    /// Write the report to `path`, as JSON if it ends in `.json` and as the
+    /// printed text otherwise.
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::to_string_pretty(&self.to_json())? + "\n"
+        } else {
+            self.to_string()
+        };
+        fs::write(path, contents)
+            .map_err(|e| format!("Failed to write report {}: {}", path.display(), e).into())
+    }
+}
+
+impl fmt::Display for SessionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.duration.as_secs();
+        writeln!(f, "Session report for {} ({})", self.device, self.source)?;
+        writeln!(
+            f,
+            "  Started   {}",
+            self.started_at.to_rfc3339_opts(SecondsFormat::Millis, true)
+        )?;
+        writeln!(
+            f,
+            "  Duration  {}:{:02}:{:02}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )?;
+        writeln!(f, "  Events    {}", self.event_summary)?;
+        writeln!(f, "  Dropped   {} lines", self.dropped)?;
+        for note in &self.notes {
+            writeln!(f, "  {}", note)?;
+        }
+        write_channel_table(f, &self.channels)
+    }
+}
+
+/// Print `channels` as an indented table, one row each.
+pub(crate) fn write_channel_table(
+    f: &mut fmt::Formatter<'_>,
+    channels: &[ChannelStats],
+) -> fmt::Result {
+    if channels.is_empty() {
+        return Ok(());
+    }
+    let name_width = channels
+        .iter()
+        .map(|channel| channel.name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("Channel".len());
+    write!(
+        f,
+        "  {:<name_width$} {:>8} {:>12} {:>12} {:>12} {:>12}",
+        "Channel", "Count", "Min", "Max", "Mean", "Std dev"
+    )?;
+    for p in PERCENTILES {
+        write!(f, " {:>12}", format!("p{}", p))?;
+    }
+    writeln!(f)?;
+    for channel in channels {
+        write!(
+            f,
+            "  {:<name_width$} {:>8} {:>12.3} {:>12.3} {:>12.3} {:>12.3}",
+            channel.name, channel.count, channel.min, channel.max, channel.mean, channel.stddev
+        )?;
+        for value in &channel.percentiles {
+            write!(f, " {:>12.3}", value)?;
+        }
+        writeln!(f)?;
+    }
+    Ok(())
+}
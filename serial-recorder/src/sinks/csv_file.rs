@@ -0,0 +1,128 @@
+use std::error::Error;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use super::{open_file, refuse_overwrite, SessionInfo, Sink, WriteMode};
+use crate::clock::{HostTime, HOST_TIME_HEADERS};
+use crate::decoder::Value;
+use crate::events::Event;
+
+/// Samples and events as two CSV files, one row per line received.
+pub struct CsvSink {
+    output: PathBuf,
+    events: PathBuf,
+    mode: WriteMode,
+    writers: Option<(csv::Writer<File>, csv::Writer<File>)>,
+}
+
+impl CsvSink {
+    pub fn new(output: PathBuf, events: PathBuf, mode: WriteMode) -> Self {
+        CsvSink {
+            output,
+            events,
+            mode,
+            writers: None,
+        }
+    }
+
+    fn writers(&mut self) -> &mut (csv::Writer<File>, csv::Writer<File>) {
+        self.writers
+            .as_mut()
+            .expect("CSV sink used before it was opened")
+    }
+}
+
+impl Sink for CsvSink {
+    fn open(&mut self, session: &SessionInfo) -> Result<(), Box<dyn Error>> {
+        // Don't leave a new samples file behind if the events file is in the way.
+        if self.mode == WriteMode::New {
+            if let Some(path) = [&self.output, &self.events]
+                .into_iter()
+                .find(|path| path.exists())
+            {
+                return Err(refuse_overwrite(path));
+            }
+        }
+
+        let mut headers: Vec<&str> = HOST_TIME_HEADERS.to_vec();
+        headers.extend(session.headers.iter().map(String::as_str));
+        let samples = open_csv(&self.output, &headers, self.mode)?;
+
+        let mut event_headers: Vec<&str> = HOST_TIME_HEADERS.to_vec();
+        event_headers.extend(["Event", "Detail"]);
+        let events = open_csv(&self.events, &event_headers, self.mode)?;
+
+        self.writers = Some((samples, events));
+        Ok(())
+    }
+
+    fn write_sample(
+        &mut self,
+        received: &HostTime,
+        values: &[Value],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut record = vec![received.rfc3339(), received.session_seconds()];
+        record.extend(values.iter().map(|value| value.to_string()));
+
+        let (samples, _) = self.writers();
+        samples.write_record(&record)?;
+        samples.flush()?;
+        Ok(())
+    }
+
+    fn write_event(&mut self, received: &HostTime, event: &Event) -> Result<(), Box<dyn Error>> {
+        let (_, events) = self.writers();
+        events.write_record([
+            received.rfc3339().as_str(),
+            received.session_seconds().as_str(),
+            event.kind.name(),
+            event.detail.as_str(),
+        ])?;
+        events.flush()?;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some((samples, events)) = self.writers.as_mut() {
+            samples.flush()?;
+            events.flush()?;
+        }
+        Ok(())
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        vec![self.output.clone(), self.events.clone()]
+    }
+}
+
+/// Open a CSV file and make sure it starts with `headers`.
+///
+/// When appending to a file that already has rows, its header must match or
+/// the columns would no longer line up.
+fn open_csv(
+    path: &Path,
+    headers: &[&str],
+    mode: WriteMode,
+) -> Result<csv::Writer<File>, Box<dyn Error>> {
+    let has_header = mode == WriteMode::Append && path.metadata().is_ok_and(|m| m.len() > 0);
+    if has_header {
+        let existing = csv::Reader::from_path(path)?.headers()?.clone();
+        if existing.iter().ne(headers.iter().copied()) {
+            return Err(format!(
+                "Can't append to {}: its columns are {:?} but this recorder writes {:?}",
+                path.display(),
+                existing.iter().collect::<Vec<_>>(),
+                headers
+            )
+            .into());
+        }
+    }
+
+    let mut writer = csv::Writer::from_writer(open_file(path, mode)?);
+    if !has_header {
+        // Print headers to the CSV file
+        writer.write_record(headers)?;
+        writer.flush()?;
+    }
+    Ok(writer)
+}
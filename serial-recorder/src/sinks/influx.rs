@@ -0,0 +1,193 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use super::{field_name, open_file, SessionInfo, Sink, WriteMode};
+use crate::clock::HostTime;
+use crate::decoder::{widen, Value};
+use crate::events::Event;
+
+/// InfluxDB line protocol, ready for `influx write --file`.
+///
+/// Samples go to a measurement named after the device and events to
+/// `<device>_events`, both timestamped in nanoseconds of host time. Whole
+/// numbers are written as signed integers, since InfluxDB 1.x doesn't take
+/// unsigned ones.
+pub struct InfluxSink {
+    path: PathBuf,
+    mode: WriteMode,
+    measurement: String,
+    tags: String,
+    fields: Vec<String>,
+    writer: Option<BufWriter<File>>,
+}
+
+impl InfluxSink {
+    pub fn new(path: PathBuf, mode: WriteMode) -> Self {
+        InfluxSink {
+            path,
+            mode,
+            measurement: String::new(),
+            tags: String::new(),
+            fields: Vec::new(),
+            writer: None,
+        }
+    }
+
+    fn write_line(
+        &mut self,
+        measurement: &str,
+        fields: &str,
+        received: &HostTime,
+    ) -> Result<(), Box<dyn Error>> {
+        let timestamp = received.wall.timestamp_nanos_opt().unwrap_or_default();
+        let writer = self
+            .writer
+            .as_mut()
+            .expect("Influx sink used before it was opened");
+        writeln!(
+            writer,
+            "{}{} {} {}",
+            measurement, self.tags, fields, timestamp
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Sink for InfluxSink {
+    fn open(&mut self, session: &SessionInfo) -> Result<(), Box<dyn Error>> {
+        self.measurement = escape_key(&field_name(&session.device));
+        self.tags = format!(",source={}", escape_key(&session.source));
+        self.fields = session
+            .headers
+            .iter()
+            .map(|h| escape_key(&field_name(h)))
+            .collect();
+        self.writer = Some(BufWriter::new(open_file(&self.path, self.mode)?));
+        Ok(())
+    }
+
+    fn write_sample(
+        &mut self,
+        received: &HostTime,
+        values: &[Value],
+    ) -> Result<(), Box<dyn Error>> {
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .zip(values)
+            .map(|(field, value)| format!("{}={}", field, field_value(value)))
+            .collect();
+        let measurement = self.measurement.clone();
+        self.write_line(&measurement, &fields.join(","), received)
+    }
+
+    fn write_event(&mut self, received: &HostTime, event: &Event) -> Result<(), Box<dyn Error>> {
+        let measurement = format!("{}_events,event={}", self.measurement, event.kind.name());
+        let fields = format!("detail={}", quote(&event.detail));
+        self.write_line(&measurement, &fields, received)
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+}
+
+fn field_value(value: &Value) -> String {
+    match value {
+        Value::U32(v) => format!("{}i", v),
+        // Only past i64::MAX does a value need the unsigned type.
+        Value::U64(v) => match i64::try_from(*v) {
+            Ok(v) => format!("{}i", v),
+            Err(_) => format!("{}u", v),
+        },
+        Value::I64(v) => format!("{}i", v),
+        Value::F32(v) => widen(*v).to_string(),
+        Value::F64(v) => v.to_string(),
+        Value::Text(v) => quote(v),
+    }
+}
+
+/// Escape a measurement name, tag or field key.
+fn escape_key(key: &str) -> String {
+    let mut escaped = String::with_capacity(key.len());
+    for c in key.chars() {
+        if matches!(c, ',' | '=' | ' ' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Quote a string field value.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::events::EventKind;
+
+    #[test]
+    fn writes_escaped_lines() {
+        let path =
+            std::env::temp_dir().join(format!("serial-recorder-influx-{}.lp", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let received = HostTime {
+            wall: "2026-03-01T12:00:01.500Z".parse().unwrap(),
+            elapsed: Duration::from_millis(1500),
+        };
+
+        let mut sink = InfluxSink::new(path.clone(), WriteMode::New);
+        sink.open(&SessionInfo {
+            device: "Bench Tacho".to_string(),
+            source: "replay of a,b=c.cap".to_string(),
+            started_at: received.wall,
+            headers: vec![
+                "Time (ms)".to_string(),
+                "Offset".to_string(),
+                "Counter".to_string(),
+                "Big Counter".to_string(),
+                "RPM".to_string(),
+                "State".to_string(),
+            ],
+        })
+        .unwrap();
+        sink.write_sample(
+            &received,
+            &[
+                Value::U32(1500),
+                Value::I64(-3),
+                Value::U64(1 << 40),
+                Value::U64(u64::MAX),
+                Value::F64(1200.5),
+                Value::Text("say \"hi\" C:\\".to_string()),
+            ],
+        )
+        .unwrap();
+        sink.write_event(&received, &Event::new(EventKind::Unparsed, "line \"12,x\""))
+            .unwrap();
+        sink.close().unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "bench_tacho,source=replay\\ of\\ a\\,b\\=c.cap time_ms=1500i,offset=-3i,\
+                 counter=1099511627776i,big_counter=18446744073709551615u,rpm=1200.5,\
+                 state=\"say \\\"hi\\\" C:\\\\\" 1772366401500000000",
+                "bench_tacho_events,event=unparsed,source=replay\\ of\\ a\\,b\\=c.cap \
+                 detail=\"line \\\"12,x\\\"\" 1772366401500000000",
+            ]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use serde_json::{json, Map};
+
+use super::{field_name, open_file, SessionInfo, Sink, WriteMode};
+use crate::clock::HostTime;
+use crate::decoder::{widen, Value};
+use crate::events::Event;
+
+/// One JSON object per line, tagged `"type": "sample"` or `"type": "event"`.
+pub struct JsonLinesSink {
+    path: PathBuf,
+    mode: WriteMode,
+    device: String,
+    fields: Vec<String>,
+    writer: Option<BufWriter<File>>,
+}
+
+impl JsonLinesSink {
+    pub fn new(path: PathBuf, mode: WriteMode) -> Self {
+        JsonLinesSink {
+            path,
+            mode,
+            device: String::new(),
+            fields: Vec::new(),
+            writer: None,
+        }
+    }
+
+    fn write_line(&mut self, line: serde_json::Value) -> Result<(), Box<dyn Error>> {
+        let writer = self
+            .writer
+            .as_mut()
+            .expect("JSON Lines sink used before it was opened");
+        serde_json::to_writer(&mut *writer, &line)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Sink for JsonLinesSink {
+    fn open(&mut self, session: &SessionInfo) -> Result<(), Box<dyn Error>> {
+        self.device = session.device.clone();
+        self.fields = session.headers.iter().map(|h| field_name(h)).collect();
+        self.writer = Some(BufWriter::new(open_file(&self.path, self.mode)?));
+        Ok(())
+    }
+
+    fn write_sample(
+        &mut self,
+        received: &HostTime,
+        values: &[Value],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut line = Map::new();
+        line.insert("type".into(), "sample".into());
+        line.insert("device".into(), self.device.clone().into());
+        line.insert("host_time".into(), received.rfc3339().into());
+        line.insert("session_time".into(), json!(received.elapsed.as_secs_f64()));
+        for (field, value) in self.fields.iter().zip(values) {
+            line.insert(field.clone(), to_json(value));
+        }
+        self.write_line(line.into())
+    }
+
+    fn write_event(&mut self, received: &HostTime, event: &Event) -> Result<(), Box<dyn Error>> {
+        self.write_line(json!({
+            "type": "event",
+            "device": self.device,
+            "host_time": received.rfc3339(),
+            "session_time": received.elapsed.as_secs_f64(),
+            "event": event.kind.name(),
+            "detail": event.detail,
+        }))
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+}
+
+fn to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::U32(v) => json!(v),
+        Value::U64(v) => json!(v),
+        Value::I64(v) => json!(v),
+        Value::F32(v) => json!(widen(*v)),
+        Value::F64(v) => json!(v),
+        Value::Text(v) => json!(v),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::events::EventKind;
+
+    #[test]
+    fn writes_one_object_per_line() {
+        let path = std::env::temp_dir().join(format!(
+            "serial-recorder-jsonl-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let received = HostTime {
+            wall: "2026-03-01T12:00:01.500Z".parse().unwrap(),
+            elapsed: Duration::from_millis(1500),
+        };
+
+        let mut sink = JsonLinesSink::new(path.clone(), WriteMode::New);
+        sink.open(&SessionInfo {
+            device: "tacho".to_string(),
+            source: "/dev/ttyUSB0".to_string(),
+            started_at: received.wall,
+            headers: vec![
+                "Time (ms)".to_string(),
+                "Offset".to_string(),
+                "Counter".to_string(),
+                "RPM".to_string(),
+                "State".to_string(),
+            ],
+        })
+        .unwrap();
+        sink.write_sample(
+            &received,
+            &[
+                Value::U32(1500),
+                Value::I64(-3),
+                Value::U64(u64::MAX),
+                Value::F32(1200.5),
+                Value::Text("say \"hi\"".to_string()),
+            ],
+        )
+        .unwrap();
+        sink.write_event(&received, &Event::new(EventKind::Timeout, "no data"))
+            .unwrap();
+        sink.close().unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                json!({
+                    "type": "sample",
+                    "device": "tacho",
+                    "host_time": "2026-03-01T12:00:01.500Z",
+                    "session_time": 1.5,
+                    "time_ms": 1500,
+                    "offset": -3,
+                    "counter": u64::MAX,
+                    "rpm": 1200.5,
+                    "state": "say \"hi\"",
+                }),
+                json!({
+                    "type": "event",
+                    "device": "tacho",
+                    "host_time": "2026-03-01T12:00:01.500Z",
+                    "session_time": 1.5,
+                    "event": "timeout",
+                    "detail": "no data",
+                }),
+            ]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+//! Where recorded samples and events end up.
+//!
+//! Every sink sees the same rows: the host time a line was received, the
+//! decoded values and whatever the stages appended. Any number of sinks can be
+//! active at once.
+
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::clock::HostTime;
+use crate::decoder::Value;
+use crate::events::Event;
+
+mod csv_file;
+mod influx;
+mod json_lines;
+mod mqtt;
+mod rotate;
+mod sqlite;
+
+pub use csv_file::CsvSink;
+pub use influx::InfluxSink;
+pub use json_lines::JsonLinesSink;
+pub use mqtt::{MqttConfig, MqttSink};
+pub use rotate::{RotatingSink, Rotation};
+pub use sqlite::SqliteSink;
+
+/// What a sink is told about the recording before the first row.
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    /// Short name of the instrument, such as "tacho".
+    pub device: String,
+    /// The port or capture file being recorded.
+    pub source: String,
+    pub started_at: DateTime<Utc>,
+    /// Headers of the decoded and stage columns, in row order.
+    pub headers: Vec<String>,
+}
+
+/// A destination for recorded rows.
+pub trait Sink {
+    /// Prepare the output, e.g. write headers. Called once before any rows.
+    fn open(&mut self, session: &SessionInfo) -> Result<(), Box<dyn Error>>;
+
+    fn write_sample(&mut self, received: &HostTime, values: &[Value])
+        -> Result<(), Box<dyn Error>>;
+
+    fn write_event(&mut self, received: &HostTime, event: &Event) -> Result<(), Box<dyn Error>>;
+
+    /// Flush and finalise the output at the end of the session.
+    fn close(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Events about the sink itself, such as files being rotated, for the
+    /// recorder to record and report like its own. Polled between reads, so
+    /// sinks with background threads collect them until asked.
+    fn notices(&mut self) -> Vec<Event> {
+        Vec::new()
+    }
+
+    /// Files the sink writes to, for rotating them.
+    fn paths(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+}
+
+/// What to do when an output file already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Refuse to touch an existing file.
+    New,
+    /// Continue an existing file, checking it has the same columns.
+    Append,
+    /// Replace an existing file.
+    Overwrite,
+}
+
+/// Open `path` for writing according to `mode`, creating parent directories.
+pub fn open_file(path: &Path, mode: WriteMode) -> Result<File, Box<dyn Error>> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut options = OpenOptions::new();
+    match mode {
+        WriteMode::New => options.write(true).create_new(true),
+        WriteMode::Append => options.append(true).create(true),
+        WriteMode::Overwrite => options.write(true).create(true).truncate(true),
+    };
+
+    options.open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => refuse_overwrite(path),
+        _ => format!("Failed to open {}: {}", path.display(), e).into(),
+    })
+}
+
+/// Refuse if any of `paths` already exists, so that when one of several
+/// outputs is in the way none of them is created.
+pub(crate) fn refuse_existing(
+    paths: impl IntoIterator<Item = PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    match paths.into_iter().find(|path| path.exists()) {
+        Some(path) => Err(refuse_overwrite(&path)),
+        None => Ok(()),
+    }
+}
+
+fn refuse_overwrite(path: &Path) -> Box<dyn Error> {
+    format!(
+        "Refusing to overwrite {}; pass --append to continue it or --force to replace it",
+        path.display()
+    )
+    .into()
+}
+
+/// The output formats that can be picked on the command line.
+pub const SINK_KINDS: [&str; 4] = ["csv", "jsonl", "influx", "sqlite"];
+
+/// Build a sink from a `kind[=path]` command line spec.
+///
+/// Without a path, the output file name is `output` with the extension for
+/// that format; CSV keeps `output` and `events` as they are.
+pub fn from_spec(
+    spec: &str,
+    output: &Path,
+    events: &Path,
+    mode: WriteMode,
+) -> Result<Box<dyn Sink + Send>, String> {
+    let (kind, path) = match spec.split_once('=') {
+        Some((kind, path)) => (kind, Some(PathBuf::from(path))),
+        None => (spec, None),
+    };
+
+    match kind {
+        "csv" => {
+            let output = path.unwrap_or_else(|| output.to_path_buf());
+            let events = events_path_for(&output, events);
+            Ok(Box::new(CsvSink::new(output, events, mode)))
+        }
+        "jsonl" => Ok(Box::new(JsonLinesSink::new(
+            path.unwrap_or_else(|| output.with_extension("jsonl")),
+            mode,
+        ))),
+        "influx" => Ok(Box::new(InfluxSink::new(
+            path.unwrap_or_else(|| output.with_extension("lp")),
+            mode,
+        ))),
+        "sqlite" => Ok(Box::new(SqliteSink::new(
+            path.unwrap_or_else(|| output.with_extension("sqlite")),
+            mode,
+        ))),
+        _ => Err(format!(
+            "Unknown sink {:?}, expected one of {}",
+            kind,
+            SINK_KINDS.join(", ")
+        )),
+    }
+}
+
+/// Keep the events file next to a CSV output that was given its own path.
+fn events_path_for(output: &Path, events: &Path) -> PathBuf {
+    match (output.parent(), events.file_name()) {
+        (Some(dir), Some(name)) if events.parent() == Some(Path::new("")) => dir.join(name),
+        _ => events.to_path_buf(),
+    }
+}
+
+/// Turn a column header like "Temperature (°F)" into a field name like
+/// "temperature_f" for formats that need identifiers.
+pub fn field_name(header: &str) -> String {
+    let mut name = String::new();
+    for c in header.chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_lowercase());
+        } else if !name.is_empty() && !name.ends_with('_') && c.is_ascii() {
+            name.push('_');
+        }
+    }
+    name.trim_end_matches('_').to_string()
+}
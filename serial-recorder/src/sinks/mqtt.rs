@@ -0,0 +1,350 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rumqttc::{Client, Event as MqttEvent, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use serde_json::json;
+
+use super::{field_name, SessionInfo, Sink};
+use crate::clock::HostTime;
+use crate::decoder::Value;
+use crate::events::{Event, EventKind};
+
+/// Requests the client queues for the connection thread before `try_publish`
+/// fails and messages go to the offline buffer instead.
+const REQUEST_CAPACITY: usize = 64;
+
+/// How long to wait between attempts to reach the broker.
+const RETRY: Duration = Duration::from_secs(2);
+
+/// How long closing waits for the last messages to go out.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where and how to publish.
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    /// Topics are `{prefix}/{device}/{channel}`.
+    pub prefix: String,
+    pub qos: QoS,
+    /// Most messages kept while the broker can't be reached; the oldest are
+    /// dropped beyond this.
+    pub buffer: usize,
+}
+
+impl MqttConfig {
+    /// Parse a `HOST[:PORT]` broker address, with the standard port 1883 if
+    /// none is given.
+    pub fn broker(address: &str) -> Result<(String, u16), String> {
+        match address.rsplit_once(':') {
+            Some((host, port)) => port
+                .parse()
+                .map(|port| (host.to_string(), port))
+                .map_err(|_| format!("Invalid port in MQTT broker {:?}", address)),
+            None => Ok((address.to_string(), 1883)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Message {
+    topic: String,
+    payload: String,
+    retain: bool,
+}
+
+/// Messages waiting for the broker, shared with the connection thread.
+#[derive(Debug, Default)]
+struct Outbox {
+    messages: VecDeque<Message>,
+    dropped: u64,
+}
+
+impl Outbox {
+    /// Queue `message`, dropping the oldest once there are more than `limit`.
+    fn push(&mut self, message: Message, limit: usize) {
+        self.messages.push_back(message);
+        if self.messages.len() > limit {
+            self.messages.pop_front();
+            self.dropped += 1;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    connected: AtomicBool,
+    /// Set when connecting fails, until the broker is reached again.
+    unreachable: AtomicBool,
+    closing: AtomicBool,
+    outbox: Mutex<Outbox>,
+}
+
+impl Shared {
+    fn outbox(&self) -> MutexGuard<'_, Outbox> {
+        self.outbox
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Hand buffered messages to the client, oldest first, until it won't
+    /// take any more.
+    fn flush(&self, client: &Client, qos: QoS) {
+        let mut outbox = self.outbox();
+        while self.connected.load(Ordering::Relaxed) {
+            let Some(message) = outbox.messages.pop_front() else {
+                break;
+            };
+            let Message {
+                topic,
+                payload,
+                retain,
+            } = message;
+            if client
+                .try_publish(topic.as_str(), qos, retain, payload.as_bytes())
+                .is_err()
+            {
+                outbox.messages.push_front(Message {
+                    topic,
+                    payload,
+                    retain,
+                });
+                break;
+            }
+        }
+    }
+}
+
+/// Publishes every sample and event to an MQTT broker.
+///
+/// Each column goes to its own retained topic, `{prefix}/{device}/{channel}`,
+/// so a new subscriber gets the latest value straight away. Events go to
+/// `{prefix}/{device}/events/{kind}` as JSON, and `{prefix}/{device}/status`
+/// says whether the recorder is online. While the broker can't be reached,
+/// messages are buffered and sent in order once it's back. Losing and
+/// reaching the broker again are reported as `output` events.
+pub struct MqttSink {
+    config: MqttConfig,
+    topic: String,
+    channels: Vec<String>,
+    shared: Arc<Shared>,
+    client: Option<(Client, Receiver<()>)>,
+    /// What happened to the connection, for [`Sink::notices`].
+    sender: Sender<Event>,
+    notices: Receiver<Event>,
+}
+
+impl MqttSink {
+    pub fn new(config: MqttConfig) -> Self {
+        let (sender, notices) = mpsc::channel();
+        MqttSink {
+            config,
+            topic: String::new(),
+            channels: Vec::new(),
+            shared: Arc::default(),
+            client: None,
+            sender,
+            notices,
+        }
+    }
+
+    fn notify(&self, detail: String) {
+        // The receiver lives as long as `self`.
+        let _ = self.sender.send(Event::new(EventKind::Output, detail));
+    }
+
+    fn publish(&self, topic: String, payload: String, retain: bool) {
+        let Some((client, _)) = &self.client else {
+            return;
+        };
+        self.shared.outbox().push(
+            Message {
+                topic,
+                payload,
+                retain,
+            },
+            self.config.buffer,
+        );
+        self.shared.flush(client, self.config.qos);
+    }
+}
+
+impl Sink for MqttSink {
+    fn open(&mut self, session: &SessionInfo) -> Result<(), Box<dyn Error>> {
+        self.topic = format!("{}/{}", self.config.prefix, session.device);
+        self.channels = session.headers.iter().map(|h| field_name(h)).collect();
+
+        let status = format!("{}/status", self.topic);
+        let id = format!("serial-recorder-{}-{}", session.device, std::process::id());
+        let mut options = MqttOptions::new(id, self.config.host.clone(), self.config.port);
+        options
+            .set_keep_alive(Duration::from_secs(30))
+            .set_last_will(LastWill::new(
+                status.clone(),
+                "offline",
+                self.config.qos,
+                true,
+            ));
+        let (client, mut connection) = Client::new(options, REQUEST_CAPACITY);
+
+        let (done, finished) = mpsc::channel();
+        let shared = Arc::clone(&self.shared);
+        let thread_client = client.clone();
+        let broker = format!("{}:{}", self.config.host, self.config.port);
+        let qos = self.config.qos;
+        let sender = self.sender.clone();
+        let notify = move |detail: String| {
+            let _ = sender.send(Event::new(EventKind::Output, detail));
+        };
+        thread::spawn(move || {
+            for notification in connection.iter() {
+                match notification {
+                    Ok(MqttEvent::Incoming(Packet::ConnAck(_))) => {
+                        if shared.unreachable.swap(false, Ordering::Relaxed) {
+                            notify(format!("Reconnected to MQTT broker {}", broker));
+                        }
+                        shared.connected.store(true, Ordering::Relaxed);
+                        let _ = thread_client.try_publish(status.as_str(), qos, true, "online");
+                        shared.flush(&thread_client, qos);
+                    }
+                    Ok(MqttEvent::Outgoing(Outgoing::Disconnect)) => break,
+                    Ok(_) => shared.flush(&thread_client, qos),
+                    Err(e) => {
+                        shared.connected.store(false, Ordering::Relaxed);
+                        if !shared.unreachable.swap(true, Ordering::Relaxed) {
+                            notify(format!("Can't reach MQTT broker {}: {}", broker, e));
+                        }
+                        if shared.closing.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        thread::sleep(RETRY);
+                    }
+                }
+            }
+            let _ = done.send(());
+        });
+
+        self.client = Some((client, finished));
+        Ok(())
+    }
+
+    fn write_sample(
+        &mut self,
+        _received: &HostTime,
+        values: &[Value],
+    ) -> Result<(), Box<dyn Error>> {
+        for (channel, value) in self.channels.iter().zip(values) {
+            let topic = format!("{}/{}", self.topic, channel);
+            self.publish(topic, value.to_string(), true);
+        }
+        Ok(())
+    }
+
+    fn write_event(&mut self, received: &HostTime, event: &Event) -> Result<(), Box<dyn Error>> {
+        let topic = format!("{}/events/{}", self.topic, event.kind.name());
+        let payload = json!({
+            "host_time": received.rfc3339(),
+            "session_time": received.elapsed.as_secs_f64(),
+            "detail": event.detail,
+        });
+        self.publish(topic, payload.to_string(), false);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some((client, finished)) = self.client.take() else {
+            return Ok(());
+        };
+        self.shared.closing.store(true, Ordering::Relaxed);
+
+        // A short session can end before the first connection is made.
+        let waiting = Instant::now();
+        while !self.shared.connected.load(Ordering::Relaxed)
+            && !self.shared.unreachable.load(Ordering::Relaxed)
+            && waiting.elapsed() < CLOSE_TIMEOUT
+        {
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        let (messages, dropped) = {
+            let mut outbox = self.shared.outbox();
+            (std::mem::take(&mut outbox.messages), outbox.dropped)
+        };
+        let mut unsent = dropped + messages.len() as u64;
+        // The connection thread is still sending, so waiting for room in the
+        // client's queue is fine now.
+        let qos = self.config.qos;
+        if self.shared.connected.load(Ordering::Relaxed) {
+            for message in messages {
+                client.publish(message.topic, qos, message.retain, message.payload)?;
+                unsent -= 1;
+            }
+            client.publish(format!("{}/status", self.topic), qos, true, "offline")?;
+        }
+        // Fails if the connection thread already gave up on the broker.
+        let _ = client.disconnect();
+
+        if finished.recv_timeout(CLOSE_TIMEOUT).is_err() {
+            self.notify(
+                "Gave up waiting for the MQTT broker to take the last messages".to_string(),
+            );
+        }
+        if unsent > 0 {
+            self.notify(format!(
+                "{} MQTT messages weren't published because the broker was unreachable",
+                unsent
+            ));
+        }
+        Ok(())
+    }
+
+    fn notices(&mut self) -> Vec<Event> {
+        self.notices.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(n: usize) -> Message {
+        Message {
+            topic: "lab/tacho/rpm".to_string(),
+            payload: n.to_string(),
+            retain: true,
+        }
+    }
+
+    #[test]
+    fn outbox_drops_the_oldest_messages() {
+        let mut outbox = Outbox::default();
+        for n in 0..5 {
+            outbox.push(message(n), 3);
+        }
+        let payloads: Vec<&str> = outbox
+            .messages
+            .iter()
+            .map(|message| message.payload.as_str())
+            .collect();
+        assert_eq!(payloads, ["2", "3", "4"]);
+        assert_eq!(outbox.dropped, 2);
+    }
+
+    #[test]
+    fn parses_broker_addresses() {
+        assert_eq!(
+            MqttConfig::broker("localhost"),
+            Ok(("localhost".to_string(), 1883))
+        );
+        assert_eq!(
+            MqttConfig::broker("10.0.0.5:8883"),
+            Ok(("10.0.0.5".to_string(), 8883))
+        );
+        assert!(MqttConfig::broker("broker:mqtt").is_err());
+    }
+}
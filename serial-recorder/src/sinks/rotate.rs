@@ -0,0 +1,482 @@
+//! Rotating output files by size or time.
+//!
+//! [`RotatingSink`] wraps any sink that writes files, closing them now and
+//! then, renaming them after the time they were started and opening fresh
+//! ones. Rotated files can be gzipped and the oldest deleted in the
+//! background, without holding up the recording.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use chrono::{DateTime, Local, TimeZone};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use super::{SessionInfo, Sink};
+use crate::clock::HostTime;
+use crate::decoder::Value;
+use crate::events::{Event, EventKind};
+
+/// When to start new output files and what to do with the old ones.
+#[derive(Debug, Clone, Default)]
+pub struct Rotation {
+    /// Start new files at multiples of this since local midnight, so a day
+    /// rotates at midnight and an hour on the hour.
+    pub every: Option<Duration>,
+    /// Start new files once any of them reaches this many bytes.
+    pub max_size: Option<u64>,
+    /// Gzip files once they've been rotated.
+    pub compress: bool,
+    /// How many rotated files to keep of each output; older ones are deleted.
+    pub keep: Option<usize>,
+}
+
+/// Gives another sink a fresh set of files now and then, so a recording that
+/// runs for weeks doesn't end up in one enormous file.
+///
+/// The files being written keep their names. Rotated ones are renamed after
+/// the time they were started, e.g. `temperature_data.20261018T000000.csv`,
+/// and the sink is opened again, so every file starts with its own headers.
+/// Setting `rotate_now` rotates before the next row, e.g. on SIGHUP. Rotated
+/// and deleted files are reported as `output` events.
+pub struct RotatingSink {
+    inner: Box<dyn Sink + Send>,
+    rotation: Rotation,
+    rotate_now: Arc<AtomicBool>,
+    session: Option<SessionInfo>,
+    /// When the current files were started.
+    started: DateTime<Local>,
+    /// When the next timed rotation is due.
+    next: Option<DateTime<Local>>,
+    /// Whether anything has been written to the current files. A new file
+    /// can already be over the size limit, e.g. an SQLite database's schema.
+    written: bool,
+    /// Compressing and pruning rotated files, in the background. Each
+    /// rotation's worker waits for the one before.
+    worker: Option<JoinHandle<()>>,
+    /// What's been rotated, compressed or deleted, for [`Sink::notices`].
+    sender: Sender<Event>,
+    notices: Receiver<Event>,
+}
+
+impl RotatingSink {
+    pub fn new(
+        inner: Box<dyn Sink + Send>,
+        rotation: Rotation,
+        rotate_now: Arc<AtomicBool>,
+    ) -> Self {
+        let (sender, notices) = mpsc::channel();
+        RotatingSink {
+            inner,
+            rotation,
+            rotate_now,
+            session: None,
+            started: Local::now(),
+            next: None,
+            written: false,
+            worker: None,
+            sender,
+            notices,
+        }
+    }
+
+    fn rotate_if_due(&mut self, received: &HostTime) -> Result<(), Box<dyn Error>> {
+        let now = received.wall.with_timezone(&Local);
+        let requested = self.rotate_now.swap(false, Ordering::Relaxed);
+        let timed = self.next.is_some_and(|next| now >= next);
+        let full = self.written
+            && self.rotation.max_size.is_some_and(|max| {
+                self.inner
+                    .paths()
+                    .iter()
+                    .any(|path| path.metadata().is_ok_and(|m| m.len() >= max))
+            });
+        if requested || timed || full {
+            self.rotate(now)?;
+        }
+        self.written = true;
+        Ok(())
+    }
+
+    fn rotate(&mut self, now: DateTime<Local>) -> Result<(), Box<dyn Error>> {
+        let session = self
+            .session
+            .clone()
+            .expect("Rotating sink used before it was opened");
+        self.inner.close()?;
+
+        let mut rotated = Vec::new();
+        for path in self.inner.paths() {
+            if !path.exists() {
+                continue;
+            }
+            let archive = archive_path(&path, &self.started)?;
+            fs::rename(&path, &archive).map_err(|e| {
+                format!(
+                    "Failed to rotate {} to {}: {}",
+                    path.display(),
+                    archive.display(),
+                    e
+                )
+            })?;
+            self.notify(format!(
+                "Rotated {} to {}",
+                path.display(),
+                archive.display()
+            ));
+            rotated.push((path, archive));
+        }
+
+        self.inner.open(&session)?;
+        self.started = now;
+        self.next = self.rotation.every.map(|every| next_rotation(now, every));
+
+        self.written = false;
+
+        let previous = self.worker.take();
+        let (compress, keep) = (self.rotation.compress, self.rotation.keep);
+        let sender = self.sender.clone();
+        self.worker = Some(thread::spawn(move || {
+            if let Some(previous) = previous {
+                let _ = previous.join();
+            }
+            let tidy = || -> Result<(), String> {
+                for (path, archive) in rotated {
+                    // Rotations faster than compression can be pruned first.
+                    if compress && archive.exists() {
+                        gzip(&archive)?;
+                    }
+                    if let Some(keep) = keep {
+                        for removed in prune(&path, keep)? {
+                            let detail = format!("Removed {}", removed.display());
+                            let _ = sender.send(Event::new(EventKind::Output, detail));
+                        }
+                    }
+                }
+                Ok(())
+            };
+            if let Err(e) = tidy() {
+                let _ = sender.send(Event::new(EventKind::Output, e));
+            }
+        }));
+        Ok(())
+    }
+
+    fn notify(&self, detail: String) {
+        // The receiver lives as long as `self`.
+        let _ = self.sender.send(Event::new(EventKind::Output, detail));
+    }
+}
+
+impl Sink for RotatingSink {
+    fn open(&mut self, session: &SessionInfo) -> Result<(), Box<dyn Error>> {
+        self.inner.open(session)?;
+        self.session = Some(session.clone());
+        self.started = Local::now();
+        self.written = false;
+        self.next = self
+            .rotation
+            .every
+            .map(|every| next_rotation(self.started, every));
+        Ok(())
+    }
+
+    fn write_sample(
+        &mut self,
+        received: &HostTime,
+        values: &[Value],
+    ) -> Result<(), Box<dyn Error>> {
+        self.rotate_if_due(received)?;
+        self.inner.write_sample(received, values)
+    }
+
+    fn write_event(&mut self, received: &HostTime, event: &Event) -> Result<(), Box<dyn Error>> {
+        self.rotate_if_due(received)?;
+        self.inner.write_event(received, event)
+    }
+
+    fn close(&mut self) -> Result<(), Box<dyn Error>> {
+        self.inner.close()?;
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                self.notify("Compressing rotated files failed".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn notices(&mut self) -> Vec<Event> {
+        let mut notices = self.inner.notices();
+        notices.extend(self.notices.try_iter());
+        notices
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        self.inner.paths()
+    }
+}
+
+/// The first multiple of `every` since local midnight that's after `now`.
+fn next_rotation(now: DateTime<Local>, every: Duration) -> DateTime<Local> {
+    let midnight = now
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .expect("Midnight exists.");
+    let midnight = Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .unwrap_or(now);
+    let step = (every.as_millis() as i64).max(1);
+    let since = (now - midnight).num_milliseconds();
+    midnight + chrono::Duration::milliseconds((since / step + 1) * step)
+}
+
+/// Where `path` goes when it's rotated: its name with the time it was
+/// started added before the extension.
+fn archive_path(path: &Path, started: &DateTime<Local>) -> Result<PathBuf, String> {
+    let (stem, extension) = split_name(path);
+    let stamp = started.format("%Y%m%dT%H%M%S").to_string();
+    // Files rotated for size can be started within the same second. Counting
+    // on from the newest keeps them in order even after older ones are pruned.
+    let n = archives(path)?
+        .iter()
+        .filter(|((time, _), _)| *time == stamp)
+        .map(|((_, n), _)| n + 1)
+        .max();
+    Ok(match n {
+        Some(n) => path.with_file_name(format!("{}.{}-{}{}", stem, stamp, n, extension)),
+        None => path.with_file_name(format!("{}.{}{}", stem, stamp, extension)),
+    })
+}
+
+/// The name of `path` without its extension, and the extension with its dot.
+fn split_name(path: &Path) -> (String, String) {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    (stem.into_owned(), extension)
+}
+
+/// The time stamp and count in a rotated copy's name, which order the copies.
+type Key = (String, u32);
+
+/// The rotated copies of `path`, compressed or not, keyed by their time stamp
+/// and count, oldest first.
+fn archives(path: &Path) -> Result<Vec<(Key, PathBuf)>, String> {
+    let (stem, extension) = split_name(path);
+    let prefix = format!("{}.", stem);
+    let key = |name: &str| -> Option<Key> {
+        let stamp = name
+            .strip_suffix(".gz")
+            .unwrap_or(name)
+            .strip_suffix(extension.as_str())?
+            .strip_prefix(prefix.as_str())?;
+        // e.g. 20261018T000000, then 20261018T000000-1 for a second file
+        // started that second.
+        let (time, n) = stamp.split_once('-').unwrap_or((stamp, "0"));
+        let valid = time.len() == 15 && time.as_bytes()[8] == b'T';
+        valid.then(|| Some((time.to_string(), n.parse().ok()?)))?
+    };
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut archives: Vec<(Key, PathBuf)> = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to list {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| Some((key(&entry.file_name().to_string_lossy())?, entry.path())))
+        .collect();
+    archives.sort();
+    Ok(archives)
+}
+
+fn gz_name(path: &Path) -> String {
+    format!(
+        "{}.gz",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    )
+}
+
+/// Replace `path` with a gzipped copy.
+fn gzip(path: &Path) -> Result<(), String> {
+    let compressed = path.with_file_name(gz_name(path));
+    let failed = |e: io::Error| format!("Failed to compress {}: {}", path.display(), e);
+
+    let mut input = BufReader::new(File::open(path).map_err(failed)?);
+    let mut output = GzEncoder::new(
+        BufWriter::new(File::create(&compressed).map_err(failed)?),
+        Compression::default(),
+    );
+    io::copy(&mut input, &mut output).map_err(failed)?;
+    output.finish().map_err(failed)?;
+    fs::remove_file(path).map_err(failed)
+}
+
+/// Delete all but the newest `keep` rotated copies of `path`, returning the
+/// files deleted.
+fn prune(path: &Path, keep: usize) -> Result<Vec<PathBuf>, String> {
+    let archives = archives(path)?;
+    // A copy being compressed is there twice for a moment.
+    let mut keys: Vec<&Key> = archives.iter().map(|(key, _)| key).collect();
+    keys.dedup();
+    let Some(excess) = keys.len().checked_sub(keep).filter(|excess| *excess > 0) else {
+        return Ok(Vec::new());
+    };
+    let oldest_kept = keys[excess];
+
+    let mut removed = Vec::new();
+    for (_, archive) in archives.iter().filter(|(key, _)| key < oldest_kept) {
+        match fs::remove_file(archive) {
+            Ok(()) => removed.push(archive.clone()),
+            // Another rotation got to it first.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to remove {}: {}", archive.display(), e)),
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for each test.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "serial-recorder-rotate-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(dir: &Path, names: &[&str]) {
+        for name in names {
+            File::create(dir.join(name)).unwrap();
+        }
+    }
+
+    fn at(hour: u32, minute: u32, second: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2026, 10, 18, hour, minute, second)
+            .unwrap()
+    }
+
+    fn names(paths: impl IntoIterator<Item = PathBuf>) -> Vec<String> {
+        paths
+            .into_iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn counts_files_started_in_the_same_second() {
+        let dir = scratch("archive-path");
+        let path = dir.join("data.csv");
+        let started = at(0, 0, 0);
+        let archive = |path: &Path| names([archive_path(path, &started).unwrap()]).remove(0);
+
+        assert_eq!(archive(&path), "data.20261018T000000.csv");
+        touch(&dir, &["data.20261018T000000.csv"]);
+        assert_eq!(archive(&path), "data.20261018T000000-1.csv");
+
+        // Compressed copies count, and pruned ones leave no gap to reuse.
+        touch(&dir, &["data.20261018T000000-4.csv.gz"]);
+        assert_eq!(archive(&path), "data.20261018T000000-5.csv");
+
+        // Only copies of this file started this second matter.
+        touch(&dir, &["data_events.20261018T000000-9.csv"]);
+        assert_eq!(archive(&path), "data.20261018T000000-5.csv");
+        assert_eq!(
+            archive(&dir.join("data_events.csv")),
+            "data_events.20261018T000000-10.csv"
+        );
+
+        // Without an extension the stamp goes at the end.
+        assert_eq!(archive(&dir.join("log")), "log.20261018T000000");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prunes_the_oldest_copies() {
+        let dir = scratch("prune");
+        let path = dir.join("data.csv");
+        touch(
+            &dir,
+            &[
+                "data.csv",
+                "data.20261018T000000-2.csv.gz",
+                "data.20261018T000000.csv.gz",
+                // Halfway through being compressed.
+                "data.20261018T000000-1.csv",
+                "data.20261018T000000-1.csv.gz",
+                "data.20261017T230000.csv.gz",
+                "data.notes.csv",
+                "data_events.20261017T230000.csv",
+            ],
+        );
+
+        let archives = archives(&path).unwrap();
+        assert_eq!(
+            names(archives.into_iter().map(|(_, path)| path)),
+            [
+                "data.20261017T230000.csv.gz",
+                "data.20261018T000000.csv.gz",
+                "data.20261018T000000-1.csv",
+                "data.20261018T000000-1.csv.gz",
+                "data.20261018T000000-2.csv.gz",
+            ]
+        );
+
+        assert_eq!(
+            names(prune(&path, 2).unwrap()),
+            ["data.20261017T230000.csv.gz", "data.20261018T000000.csv.gz"]
+        );
+        assert!(prune(&path, 2).unwrap().is_empty());
+        assert!(dir.join("data.20261018T000000-1.csv").exists());
+        assert!(dir.join("data.csv").exists());
+        assert!(dir.join("data_events.20261017T230000.csv").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compresses_in_place() {
+        let dir = scratch("gzip");
+        let path = dir.join("data.20261018T000000.csv");
+        fs::write(&path, "RPM\n1200\n").unwrap();
+        gzip(&path).unwrap();
+        assert!(!path.exists());
+        assert!(dir.join("data.20261018T000000.csv.gz").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotates_on_multiples_since_midnight() {
+        let hour = Duration::from_secs(3600);
+        assert_eq!(next_rotation(at(10, 17, 30), hour), at(11, 0, 0));
+        assert_eq!(next_rotation(at(10, 0, 0), hour), at(11, 0, 0));
+        assert_eq!(
+            next_rotation(at(10, 17, 30), Duration::from_secs(15 * 60)),
+            at(10, 30, 0)
+        );
+        assert_eq!(
+            next_rotation(at(10, 17, 30), 24 * hour),
+            at(0, 0, 0) + chrono::Duration::days(1)
+        );
+    }
+}
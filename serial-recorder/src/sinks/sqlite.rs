@@ -0,0 +1,281 @@
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::{SecondsFormat, Utc};
+use rusqlite::types::ToSqlOutput;
+use rusqlite::{params, params_from_iter, Connection};
+
+use super::{field_name, refuse_overwrite, SessionInfo, Sink, WriteMode};
+use crate::clock::HostTime;
+use crate::decoder::{widen, Value};
+use crate::events::Event;
+
+/// A SQLite database with `sessions`, `samples` and `events` tables.
+///
+/// Appending to an existing database adds a new session; columns for a device
+/// that hasn't been recorded into it before are added to `samples`.
+pub struct SqliteSink {
+    path: PathBuf,
+    mode: WriteMode,
+    connection: Option<Connection>,
+    session_id: i64,
+    insert_sample: String,
+}
+
+impl SqliteSink {
+    pub fn new(path: PathBuf, mode: WriteMode) -> Self {
+        SqliteSink {
+            path,
+            mode,
+            connection: None,
+            session_id: 0,
+            insert_sample: String::new(),
+        }
+    }
+
+    fn connection(&self) -> &Connection {
+        self.connection
+            .as_ref()
+            .expect("SQLite sink used before it was opened")
+    }
+}
+
+impl Sink for SqliteSink {
+    fn open(&mut self, session: &SessionInfo) -> Result<(), Box<dyn Error>> {
+        if self.path.exists() {
+            match self.mode {
+                WriteMode::New => return Err(refuse_overwrite(&self.path)),
+                WriteMode::Append => (),
+                WriteMode::Overwrite => fs::remove_file(&self.path)?,
+            }
+        } else if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        let connection = Connection::open(&self.path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS sessions (
+                id INTEGER PRIMARY KEY,
+                device TEXT NOT NULL,
+                source TEXT NOT NULL,
+                started_at TEXT NOT NULL,
+                ended_at TEXT
+            );
+            CREATE TABLE IF NOT EXISTS samples (
+                session_id INTEGER NOT NULL REFERENCES sessions(id),
+                host_time TEXT NOT NULL,
+                session_time REAL NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS events (
+                session_id INTEGER NOT NULL REFERENCES sessions(id),
+                host_time TEXT NOT NULL,
+                session_time REAL NOT NULL,
+                event TEXT NOT NULL,
+                detail TEXT NOT NULL
+            );",
+        )?;
+
+        connection.execute(
+            "INSERT INTO sessions (device, source, started_at) VALUES (?1, ?2, ?3)",
+            params![
+                session.device,
+                session.source,
+                session
+                    .started_at
+                    .to_rfc3339_opts(SecondsFormat::Millis, true)
+            ],
+        )?;
+        self.session_id = connection.last_insert_rowid();
+
+        // Make sure every column this device produces exists.
+        let existing: Vec<String> = connection
+            .prepare("SELECT name FROM pragma_table_info('samples')")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        let columns: Vec<String> = session.headers.iter().map(|h| field_name(h)).collect();
+        for column in &columns {
+            if !existing.contains(column) {
+                connection.execute(
+                    &format!("ALTER TABLE samples ADD COLUMN \"{}\"", column),
+                    [],
+                )?;
+            }
+        }
+
+        let names: String = columns.iter().map(|c| format!(", \"{}\"", c)).collect();
+        let placeholders: String = (0..columns.len())
+            .map(|i| format!(", ?{}", i + 4))
+            .collect();
+        self.insert_sample = format!(
+            "INSERT INTO samples (session_id, host_time, session_time{}) VALUES (?1, ?2, ?3{})",
+            names, placeholders
+        );
+
+        self.connection = Some(connection);
+        Ok(())
+    }
+
+    fn write_sample(
+        &mut self,
+        received: &HostTime,
+        values: &[Value],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut row: Vec<ToSqlOutput> = vec![
+            self.session_id.into(),
+            received.rfc3339().into(),
+            received.elapsed.as_secs_f64().into(),
+        ];
+        row.extend(values.iter().map(|value| match value {
+            Value::U32(v) => ToSqlOutput::from(*v),
+            // SQLite integers are signed, so anything bigger is kept as text
+            // rather than wrapping round to a negative number.
+            Value::U64(v) => match i64::try_from(*v) {
+                Ok(v) => ToSqlOutput::from(v),
+                Err(_) => ToSqlOutput::from(v.to_string()),
+            },
+            Value::I64(v) => ToSqlOutput::from(*v),
+            Value::F32(v) => ToSqlOutput::from(widen(*v)),
+            Value::F64(v) => ToSqlOutput::from(*v),
+            Value::Text(v) => ToSqlOutput::from(v.clone()),
+        }));
+
+        let connection = self.connection();
+        connection
+            .prepare_cached(&self.insert_sample)?
+            .execute(params_from_iter(row))?;
+        Ok(())
+    }
+
+    fn write_event(&mut self, received: &HostTime, event: &Event) -> Result<(), Box<dyn Error>> {
+        self.connection().execute(
+            "INSERT INTO events (session_id, host_time, session_time, event, detail)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                self.session_id,
+                received.rfc3339(),
+                received.elapsed.as_secs_f64(),
+                event.kind.name(),
+                event.detail
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(connection) = self.connection.take() {
+            connection.execute(
+                "UPDATE sessions SET ended_at = ?1 WHERE id = ?2",
+                params![
+                    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                    self.session_id
+                ],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::events::EventKind;
+
+    #[test]
+    fn stores_samples_and_events() {
+        let path =
+            std::env::temp_dir().join(format!("serial-recorder-sqlite-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let started_at = "2026-03-01T12:00:00Z".parse().unwrap();
+        let received = HostTime {
+            wall: "2026-03-01T12:00:01.500Z".parse().unwrap(),
+            elapsed: Duration::from_millis(1500),
+        };
+
+        let mut sink = SqliteSink::new(path.clone(), WriteMode::New);
+        sink.open(&SessionInfo {
+            device: "tacho".to_string(),
+            source: "/dev/ttyUSB0".to_string(),
+            started_at,
+            headers: vec![
+                "Time (ms)".to_string(),
+                "Offset".to_string(),
+                "Counter".to_string(),
+                "Big Counter".to_string(),
+                "RPM".to_string(),
+                "State".to_string(),
+            ],
+        })
+        .unwrap();
+        sink.write_sample(
+            &received,
+            &[
+                Value::U32(1500),
+                Value::I64(-3),
+                Value::U64(1 << 40),
+                Value::U64(u64::MAX),
+                Value::F32(1200.5),
+                Value::Text("idle".to_string()),
+            ],
+        )
+        .unwrap();
+        sink.write_event(&received, &Event::new(EventKind::Timeout, "no data"))
+            .unwrap();
+        sink.close().unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let sample: (i64, String, f64, i64, i64, i64, String, f64, String) = connection
+            .query_row(
+                "SELECT session_id, host_time, session_time, time_ms, offset, counter,
+                        big_counter, rpm, state FROM samples",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                        row.get(6)?,
+                        row.get(7)?,
+                        row.get(8)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            sample,
+            (
+                1,
+                "2026-03-01T12:00:01.500Z".to_string(),
+                1.5,
+                1500,
+                -3,
+                1 << 40,
+                u64::MAX.to_string(),
+                1200.5,
+                "idle".to_string(),
+            )
+        );
+        let event: (String, String) = connection
+            .query_row("SELECT event, detail FROM events", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(event, ("timeout".to_string(), "no data".to_string()));
+        let ended: Option<String> = connection
+            .query_row("SELECT ended_at FROM sessions", [], |row| row.get(0))
+            .unwrap();
+        assert!(ended.is_some());
+
+        drop(connection);
+        fs::remove_file(&path).unwrap();
+    }
+}
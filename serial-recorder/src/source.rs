@@ -0,0 +1,28 @@
+use std::io::{self, Read};
+use std::sync::atomic::AtomicBool;
+
+/// Where a recorder's bytes come from.
+pub trait Source: Read {
+    /// Called when a read fails with anything but a timeout, or returns no
+    /// data. Return `Some(description)` once the source is usable again, or
+    /// `None` if it has ended for good or `stop` was set while waiting.
+    fn recover(&mut self, error: &io::Error, stop: &AtomicBool) -> Option<String> {
+        let _ = (error, stop);
+        None
+    }
+
+    /// Whether `recover` can bring the source back, so that running out of
+    /// data means the device went away rather than that the input ended.
+    fn reconnects(&self) -> bool {
+        false
+    }
+
+    /// Write `bytes` to the device.
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let _ = bytes;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this source can't be written to",
+        ))
+    }
+}
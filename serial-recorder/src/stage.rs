@@ -0,0 +1,27 @@
+use crate::clock::HostTime;
+use crate::decoder::Value;
+use crate::events::Event;
+
+/// Post-processing applied to every decoded sample before it's written.
+///
+/// Stages run in order after the decoder. Each one can look at the values
+/// produced so far and appends its own columns to the end of the row.
+pub trait Stage {
+    /// Headers for the columns this stage appends.
+    fn headers(&self) -> Vec<String>;
+
+    /// Append this stage's values to `values`, returning anything worth
+    /// recording as an event.
+    fn process(&mut self, received: &HostTime, values: &mut Vec<Value>) -> Vec<Event>;
+
+    /// Anything worth printing after the sample just processed, such as a
+    /// periodic progress report.
+    fn progress(&mut self) -> Option<String> {
+        None
+    }
+
+    /// Anything worth printing once the recording ends.
+    fn summary(&self) -> Option<String> {
+        None
+    }
+}
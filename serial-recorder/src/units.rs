@@ -0,0 +1,107 @@
+//! Parsing the durations and sizes given on the command line and in rules,
+//! such as `30s` or `100M`.
+
+use std::time::Duration;
+
+/// Parse a duration such as "250ms", "30s", "5m", "1.5h", "1d" or a bare
+/// number of seconds.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration {:?}, expected e.g. 30s or 5m", text))?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        "d" => number * 86400.0,
+        _ => {
+            return Err(format!(
+                "Unknown unit {:?} in duration {:?}, expected ms, s, m, h or d",
+                unit, text
+            ))
+        }
+    };
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Parse a duration that something happens every so often, which can't be
+/// zero.
+pub fn parse_interval(text: &str) -> Result<Duration, String> {
+    match parse_duration(text)? {
+        interval if interval.is_zero() => Err(format!(
+            "Interval {:?} must be longer than zero",
+            text.trim()
+        )),
+        interval => Ok(interval),
+    }
+}
+
+/// Parse a size such as "500K", "100M", "1.5G" or a bare number of bytes.
+pub fn parse_bytes(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size {:?}, expected e.g. 500K or 100M", text))?;
+    let scale: u64 = match unit.trim().trim_end_matches(['B', 'b']) {
+        "" => 1,
+        "K" | "k" => 1 << 10,
+        "M" | "m" => 1 << 20,
+        "G" | "g" => 1 << 30,
+        _ => {
+            return Err(format!(
+                "Unknown unit {:?} in size {:?}, expected K, M or G",
+                unit, text
+            ))
+        }
+    };
+    match (number * scale as f64) as u64 {
+        0 => Err(format!("Size {:?} must be more than zero", text)),
+        bytes => Ok(bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration(" 1d "), Ok(Duration::from_secs(86400)));
+        assert!(parse_duration("5 weeks").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[test]
+    fn parses_intervals() {
+        assert_eq!(parse_interval("2s"), Ok(Duration::from_secs(2)));
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("0").is_err());
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_bytes("12"), Ok(12));
+        assert_eq!(parse_bytes("500K"), Ok(500 << 10));
+        assert_eq!(parse_bytes("100MB"), Ok(100 << 20));
+        assert_eq!(parse_bytes("1.5 m"), Ok(3 << 19));
+        assert_eq!(parse_bytes("2G"), Ok(2 << 30));
+        assert!(parse_bytes("0").is_err());
+        assert!(parse_bytes("10X").is_err());
+        assert!(parse_bytes("K").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use crate::events::{Event, EventKind};
+
+/// Notices when a device stops sending valid samples.
+///
+/// A hung firmware or a sensor failing silently still leaves the port open,
+/// so the only sign is that samples stop arriving. The stream goes stale once
+/// no sample has arrived for `intervals` times the expected interval, and
+/// fresh again with the next sample.
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    expected: Duration,
+    intervals: u32,
+    stop_when_stale: bool,
+    /// Session time of the last valid sample, or of the start or last
+    /// reconnect if there hasn't been one since.
+    last_sample: Duration,
+    stale: bool,
+}
+
+impl Watchdog {
+    pub fn new(expected: Duration, intervals: u32, stop_when_stale: bool) -> Self {
+        Watchdog {
+            expected,
+            intervals,
+            stop_when_stale,
+            last_sample: Duration::ZERO,
+            stale: false,
+        }
+    }
+
+    /// Whether the recording should end once the stream goes stale.
+    pub fn stops_recording(&self) -> bool {
+        self.stop_when_stale
+    }
+
+    /// How long the stream can go without a sample before it's stale.
+    pub fn limit(&self) -> Duration {
+        self.expected * self.intervals
+    }
+
+    /// Note a valid sample, returning an event if the stream was stale.
+    pub fn sample(&mut self, at: Duration) -> Option<Event> {
+        let silent = at.saturating_sub(self.last_sample);
+        self.last_sample = at;
+        if !std::mem::take(&mut self.stale) {
+            return None;
+        }
+        Some(Event::new(
+            EventKind::Resumed,
+            format!(
+                "samples resumed after {:.1} s without one",
+                silent.as_secs_f64()
+            ),
+        ))
+    }
+
+    /// Start the count again, e.g. after reconnecting, so the time spent
+    /// disconnected doesn't count against the device.
+    pub fn reset(&mut self, at: Duration) {
+        self.last_sample = at;
+        self.stale = false;
+    }
+
+    /// Returns an event the first time the stream is found stale.
+    pub fn check(&mut self, now: Duration) -> Option<Event> {
+        let silent = now.saturating_sub(self.last_sample);
+        if self.stale || silent <= self.limit() {
+            return None;
+        }
+        self.stale = true;
+        Some(Event::new(
+            EventKind::Stale,
+            format!(
+                "no valid sample for {:.1} s, expected one every {:.1} s",
+                silent.as_secs_f64(),
+                self.expected.as_secs_f64()
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn goes_stale_once() {
+        let mut watchdog = Watchdog::new(secs(5), 3, false);
+        assert_eq!(watchdog.limit(), secs(15));
+        assert_eq!(watchdog.sample(secs(4)), None);
+        assert_eq!(watchdog.check(secs(19)), None);
+
+        let event = watchdog.check(secs(20)).unwrap();
+        assert_eq!(event.kind, EventKind::Stale);
+        assert!(event.detail.contains("16.0 s"), "{}", event.detail);
+        assert_eq!(watchdog.check(secs(30)), None);
+    }
+
+    #[test]
+    fn resumes_with_the_next_sample() {
+        let mut watchdog = Watchdog::new(secs(5), 3, false);
+        watchdog.check(secs(20));
+
+        let event = watchdog.sample(secs(32)).unwrap();
+        assert_eq!(event.kind, EventKind::Resumed);
+        assert!(event.detail.contains("32.0 s"), "{}", event.detail);
+        assert_eq!(watchdog.sample(secs(37)), None);
+        // The count starts again from the sample that resumed the stream.
+        assert_eq!(watchdog.check(secs(52)), None);
+        assert_eq!(watchdog.check(secs(53)).unwrap().kind, EventKind::Stale);
+    }
+
+    #[test]
+    fn reset_forgets_the_silence() {
+        let mut watchdog = Watchdog::new(secs(5), 3, false);
+        watchdog.check(secs(20));
+
+        // Reconnecting clears the stale state without a resumed event.
+        watchdog.reset(secs(60));
+        assert_eq!(watchdog.check(secs(75)), None);
+        assert_eq!(watchdog.sample(secs(70)), None);
+        assert_eq!(watchdog.check(secs(86)).unwrap().kind, EventKind::Stale);
+    }
+}